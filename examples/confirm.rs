@@ -39,7 +39,7 @@ async fn main() {
 }
 
 // pipeline input
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct NewUser {
     internal_id: i32,
     id: Option<String>,
@@ -47,17 +47,6 @@ struct NewUser {
     role: Option<Vec<UserRole>>,
 }
 
-impl Default for NewUser {
-    fn default() -> Self {
-        Self {
-            internal_id: 0,
-            id: None,
-            username: None,
-            role: None,
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 enum UserRole {
     Admin,
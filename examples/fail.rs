@@ -28,7 +28,7 @@ async fn main() {
 
 // pipeline input
 // Must be clonable. A clone of the data is passed to any pipe that requires it
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct NewUser {
     internal_id: i32,
     id: Option<String>,
@@ -36,17 +36,6 @@ struct NewUser {
     role: Option<Vec<UserRole>>,
 }
 
-impl Default for NewUser {
-    fn default() -> Self {
-        Self {
-            internal_id: 0,
-            id: None,
-            username: None,
-            role: None,
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 enum UserRole {
     Admin,
@@ -31,7 +31,7 @@ async fn main() {
 
 // Pipeline input
 // Must be cloneable. A clone of the data is passed to any pipe that requires it
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct NewUser {
     internal_id: i32,
     id: Option<String>,
@@ -39,17 +39,6 @@ struct NewUser {
     role: Option<Vec<UserRole>>,
 }
 
-impl Default for NewUser {
-    fn default() -> Self {
-        Self {
-            internal_id: 0,
-            id: None,
-            username: None,
-            role: None,
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 enum UserRole {
     Admin,
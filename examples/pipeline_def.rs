@@ -0,0 +1,28 @@
+use fama::{PipeContent, PipelineDef};
+
+#[tokio::main]
+async fn main() {
+    // 1. Record the pipes once. Nothing runs at this point
+    let def = PipelineDef::new()
+        .next_fn(|order: Order| async move { !order.items.is_empty() })
+        .through_fn(|mut order: Order, pipe: PipeContent| async move {
+            order.total = order.items.iter().sum();
+            pipe.store(order).await;
+        });
+
+    // 2. Run the same definition against many contents
+    for items in [vec![3, 4], vec![], vec![10]] {
+        let pipeline = def.run(Order { items, total: 0 }).await;
+        println!(
+            "completed: {}, order: {:?}",
+            pipeline.confirm(),
+            pipeline.deliver().await
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    items: Vec<u32>,
+    total: u32,
+}
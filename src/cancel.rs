@@ -0,0 +1,93 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use tokio::sync::Notify;
+
+/// Signals a pipeline that the remaining pipes should not run
+///
+/// The pipeline checks the token before each pipe and ends the flow
+/// with `FlowOutcome::Cancelled`. A pipe can take the token as an
+/// argument to stop its own work early. Clones share the same state.
+///
+/// ```rust
+///# use fama::{CancellationToken, Pipeline};
+///# #[tokio::main]
+///# async fn main() {
+/// let token = CancellationToken::new();
+///
+/// let pipeline = Pipeline::pass(0)
+///     .await
+///     .with_cancellation(token.clone())
+///     .await
+///     .through_fn(|token: CancellationToken| async move {
+///         token.cancel(); // the client went away
+///     })
+///     .await
+///     .store_fn(|num: i32| async move { num + 1 })
+///     .await;
+///
+/// assert!(pipeline.outcome().is_cancelled());
+/// assert_eq!(pipeline.deliver().await, 0);
+///# }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the pipelines using this token
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Waits until the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancelled() {
+        let token = CancellationToken::new();
+        let waiting = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!waiting.is_finished());
+
+        token.cancel();
+        waiting.await.unwrap();
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use futures::future::BoxFuture;
+
+use crate::{PipeContent, Reason, pipeline::PipeFnHandler};
+
+pub(crate) type Compensate =
+    Arc<dyn Fn(PipeContent) -> BoxFuture<'static, Result<(), Reason>> + Send + Sync>;
+
+/// The result of a compensation handler
+#[derive(Debug, Clone)]
+pub struct Compensation {
+    /// The position of the pipe the handler was registered for
+    pub pipe: usize,
+    /// The error returned by the handler, if any
+    pub result: Result<(), Reason>,
+}
+
+impl Compensation {
+    pub fn succeeded(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Wraps a compensation handler. The arguments are resolved from
+/// the pipe content when the handler runs
+pub(crate) fn compensate_with<H, Args, E>(handler: H) -> Compensate
+where
+    H: PipeFnHandler<Args, Result<(), E>>,
+    Args: busybody::Resolver + Send + 'static,
+    E: Send + Sync + 'static,
+{
+    let handler = Arc::new(Mutex::new(handler));
+    Arc::new(move |pipe: PipeContent| {
+        let handler = handler.clone();
+        Box::pin(async move {
+            let args = Args::resolve(pipe.container()).await;
+            let future = handler
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pipe_fn_handle(args);
+            future.await.map_err(Reason::new)
+        })
+    })
+}
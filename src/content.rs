@@ -1,8 +1,75 @@
 use busybody::ServiceContainer;
-use std::sync::Arc;
+use futures::future::BoxFuture;
+use std::{
+    any::{Any, TypeId, type_name},
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
+use crate::{
+    compensation::Compensate,
+    outcome::{FlowOutcome, Reason, StopReason},
+};
+
+/// Orders the writes made through `PipeContent::store` across all pipelines
+static WRITE_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone)]
+pub struct PipeContent {
+    container: Arc<ServiceContainer>,
+    stored: Arc<Mutex<HashMap<TypeId, StoredEntry>>>,
+    exported: Arc<Mutex<HashMap<TypeId, StoredEntry>>>,
+    /// Compensation handlers of the nested runs of the current pipe
+    handed_up: Arc<Mutex<Vec<Compensate>>>,
+}
+
+/// The last value of a type written through `PipeContent::store`
 #[derive(Clone)]
-pub struct PipeContent(pub(crate) Arc<ServiceContainer>);
+pub(crate) struct StoredEntry {
+    pub(crate) type_id: TypeId,
+    pub(crate) seq: u64,
+    pub(crate) value: Arc<dyn StoredValue>,
+}
+
+/// A type erased value that can be stored in another `PipeContent`
+pub(crate) trait StoredValue: Send + Sync {
+    fn type_name(&self) -> &'static str;
+
+    fn as_any(&self) -> &(dyn Any + Send + Sync);
+
+    fn store_into<'a>(&'a self, pipe: &'a PipeContent) -> BoxFuture<'a, ()>;
+}
+
+struct Stored<T>(T);
+
+impl<T: Clone + Send + Sync + 'static> StoredValue for Stored<T> {
+    fn type_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    fn as_any(&self) -> &(dyn Any + Send + Sync) {
+        &self.0
+    }
+
+    fn store_into<'a>(&'a self, pipe: &'a PipeContent) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            pipe.store(self.0.clone()).await;
+        })
+    }
+}
+
+impl StoredEntry {
+    pub(crate) fn new<T: Clone + Send + Sync + 'static>(value: T) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            seq: WRITE_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            value: Arc::new(Stored(value)),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, PartialOrd)]
 pub(crate) enum PipeState {
@@ -22,9 +89,14 @@ impl busybody::Resolver for PipeContent {
 
 impl PipeContent {
     pub(crate) async fn make() -> Self {
-        let container = Arc::new(ServiceContainer::proxy());
-        let pipe = Self(container);
+        let pipe = Self {
+            container: Arc::new(ServiceContainer::proxy()),
+            stored: Arc::default(),
+            exported: Arc::default(),
+            handed_up: Arc::default(),
+        };
         pipe.container().set(PipeState::Run).await;
+        pipe.container().set_type(FlowOutcome::Completed).await;
         pipe.container().set_type(pipe.clone()).await;
         pipe
     }
@@ -33,25 +105,139 @@ impl PipeContent {
         T: Clone + Send + Sync + 'static,
     {
         let pipe = Self::make().await;
-        pipe.container().set_type(content).await;
+        pipe.store(content).await;
+
+        pipe
+    }
+
+    /// Creates a new content holding a copy of every value
+    /// stored in this one
+    pub(crate) async fn fork(&self) -> Self {
+        let pipe = Self::make().await;
+        for entry in self.stored_entries() {
+            entry.value.store_into(&pipe).await;
+        }
 
         pipe
     }
+
+    /// Keeps the compensation handlers of a nested run until the
+    /// pipeline this content belongs to takes them over
+    pub(crate) fn hand_up(&self, compensations: impl IntoIterator<Item = Compensate>) {
+        self.handed_up
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(compensations);
+    }
+
+    pub(crate) fn take_handed_up(&self) -> Vec<Compensate> {
+        std::mem::take(
+            &mut *self
+                .handed_up
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    /// Returns the last value of each type written through `store`
+    pub(crate) fn stored_entries(&self) -> Vec<StoredEntry> {
+        self.stored
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the values written through `store` since `seq`
+    pub(crate) fn stored_since(&self, seq: u64) -> Vec<StoredEntry> {
+        let mut entries: Vec<StoredEntry> = self
+            .stored_entries()
+            .into_iter()
+            .filter(|entry| entry.seq >= seq)
+            .collect();
+        entries.sort_by_key(|entry| entry.seq);
+        entries
+    }
+
+    /// Returns the values written through `export`, oldest first
+    pub(crate) fn exported_entries(&self) -> Vec<StoredEntry> {
+        let mut entries: Vec<StoredEntry> = self
+            .exported
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect();
+        entries.sort_by_key(|entry| entry.seq);
+        entries
+    }
+
+    /// The sequence number of the next write
+    pub(crate) fn write_sequence() -> u64 {
+        WRITE_SEQUENCE.load(Ordering::Relaxed)
+    }
+
     /// Returns a busybody's ServiceContainer
     pub fn container(&self) -> &Arc<ServiceContainer> {
-        &self.0
+        &self.container
     }
 
+    /// Stores the value for the pipes that follow.
+    /// Values stored this way are also seen by concurrent and nested
+    /// pipelines, unlike values set directly on the container
     pub async fn store<T: Clone + Send + Sync + 'static>(&self, data: T) -> &Self {
+        self.stored
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(TypeId::of::<T>(), StoredEntry::new(data.clone()));
         self.container().set_type(data).await;
         self
     }
 
+    /// Stores the value and hands it to the parent pipeline once this
+    /// nested pipeline is done. Other writes stay in the nested pipeline.
+    /// See `Pipeline::through_pipeline`
+    pub async fn export<T: Clone + Send + Sync + 'static>(&self, data: T) -> &Self {
+        self.exported
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(TypeId::of::<T>(), StoredEntry::new(data.clone()));
+        self.store(data).await
+    }
+
     /// Notify the pipeline to stop flowing the content
     pub async fn stop_the_flow(&self) {
+        self.halt(StopReason::Unspecified).await;
+    }
+
+    /// Notify the pipeline to stop flowing the content and
+    /// record why. The reason ends up in `Pipeline::outcome`
+    pub async fn stop_the_flow_with<R: Send + Sync + 'static>(&self, reason: R) {
+        self.halt(StopReason::Custom(Reason::new(reason))).await;
+    }
+
+    pub(crate) async fn halt(&self, reason: StopReason) {
+        self.container().set_type(reason).await;
         self.container().set(PipeState::Stop).await;
     }
 
+    /// Puts the flow back into the run state
+    pub(crate) async fn resume(&self) {
+        self.container().set(PipeState::Run).await;
+    }
+
+    pub(crate) async fn is_running(&self) -> bool {
+        *self.container().get::<PipeState>().await.unwrap() == PipeState::Run
+    }
+
+    pub(crate) async fn stop_reason(&self) -> StopReason {
+        self.container()
+            .get_type::<StopReason>()
+            .await
+            .unwrap_or_default()
+    }
+
     /// Alias for `stop_the_flow`
     pub async fn stop(&self) {
         self.stop_the_flow().await;
@@ -84,4 +270,51 @@ mod test {
             PipeState::Stop
         );
     }
+
+    #[tokio::test]
+    async fn test_flow_stop_with_reason() {
+        let pipe = PipeContent::make().await;
+        pipe.stop_the_flow_with("username is required").await;
+
+        assert!(!pipe.is_running().await);
+        assert_eq!(
+            pipe.stop_reason().await.custom().and_then(Reason::as_str),
+            Some("username is required")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fork() {
+        let pipe = PipeContent::new(1_i32).await;
+        pipe.store("parent".to_string()).await;
+
+        let fork = pipe.fork().await;
+        let since = PipeContent::write_sequence();
+        fork.store(2_i32).await;
+
+        assert_eq!(
+            fork.container().get_type::<String>().await.unwrap(),
+            "parent"
+        );
+        assert_eq!(fork.container().get_type::<i32>().await, Some(2));
+        assert_eq!(pipe.container().get_type::<i32>().await, Some(1));
+
+        let written = fork.stored_since(since);
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].value.type_name(), "i32");
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let pipe = PipeContent::new(1_i32).await;
+        pipe.export("done".to_string()).await;
+        pipe.export(2_i32).await;
+        pipe.store(3_u8).await;
+
+        let exported = pipe.exported_entries();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0].value.type_name(), "alloc::string::String");
+        assert_eq!(exported[1].value.type_name(), "i32");
+        assert_eq!(pipe.container().get_type::<i32>().await, Some(2));
+    }
 }
@@ -0,0 +1,99 @@
+use std::{ops::Deref, time::Duration};
+
+/// What happened to a pipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeStatus {
+    /// The pipe was called and the flow carried on
+    Ran,
+    /// The pipe was not called because the flow had been stopped, or it
+    /// ended before the pipe: cancelled, out of time or stopped by an
+    /// interceptor
+    Skipped,
+    /// The pipe was called and it stopped the flow
+    Stopped,
+}
+
+/// A single entry in the execution log
+#[derive(Debug, Clone)]
+pub struct PipeRecord {
+    /// The position of the pipe in the pipeline
+    pub index: usize,
+    /// The pipe name, when the pipe has one
+    pub name: Option<String>,
+    /// What the pipe does, when it was described
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub status: PipeStatus,
+    /// Time between the start of the pipeline and the start of the pipe
+    pub offset: Duration,
+    /// Time spent in the pipe. Zero for skipped pipes
+    pub duration: Duration,
+    /// Number of times the pipe was called. Above one when the pipe was retried
+    pub attempts: u32,
+    /// The type names of the values the pipe wrote through `PipeContent::store`
+    pub stored: Vec<&'static str>,
+}
+
+/// The ordered list of pipes a pipeline went through
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionLog(Vec<PipeRecord>);
+
+impl ExecutionLog {
+    pub(crate) fn push(&mut self, record: PipeRecord) {
+        self.0.push(record);
+    }
+
+    /// Returns the pipes that were called, including the one that stopped the flow
+    pub fn ran(&self) -> impl Iterator<Item = &PipeRecord> {
+        self.0.iter().filter(|r| r.status != PipeStatus::Skipped)
+    }
+
+    /// Returns the pipes that were not called
+    pub fn skipped(&self) -> impl Iterator<Item = &PipeRecord> {
+        self.0.iter().filter(|r| r.status == PipeStatus::Skipped)
+    }
+
+    /// Returns the first pipe with the given name
+    pub fn by_name(&self, name: &str) -> Option<&PipeRecord> {
+        self.0.iter().find(|r| r.name.as_deref() == Some(name))
+    }
+
+    /// Returns the pipes with the given tag
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a PipeRecord> {
+        self.0
+            .iter()
+            .filter(move |r| r.tags.iter().any(|t| t == tag))
+    }
+
+    /// Returns the pipe that stopped the flow
+    pub fn stopped_at(&self) -> Option<&PipeRecord> {
+        self.0.iter().find(|r| r.status == PipeStatus::Stopped)
+    }
+
+    /// Returns true when every pipe ran and none stopped the flow
+    pub fn went_through_all(&self) -> bool {
+        self.0.iter().all(|r| r.status == PipeStatus::Ran)
+    }
+
+    /// Returns the total time spent in the pipes
+    pub fn total_duration(&self) -> Duration {
+        self.0.iter().map(|r| r.duration).sum()
+    }
+}
+
+impl Deref for ExecutionLog {
+    type Target = [PipeRecord];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> IntoIterator for &'a ExecutionLog {
+    type Item = &'a PipeRecord;
+    type IntoIter = std::slice::Iter<'a, PipeRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
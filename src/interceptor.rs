@@ -0,0 +1,197 @@
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+
+use crate::{FlowOutcome, PipeContent};
+
+static INTERCEPTORS: RwLock<Vec<Arc<dyn Interceptor>>> = RwLock::new(Vec::new());
+
+/// The pipe an interceptor is called for
+#[derive(Debug, Clone)]
+pub struct PipeInfo {
+    /// The position of the pipe in the pipeline
+    pub index: usize,
+    /// The pipe name, when the pipe has one
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    /// The type name of the pipeline content
+    pub content: &'static str,
+    /// When the pipe started. Lets `Interceptor::after` time the pipe
+    pub started: Instant,
+}
+
+/// Runs code around every pipe of a pipeline
+///
+/// `before` is called before the pipe and can stop the flow, in which
+/// case the pipe is not called. `after` is called once the pipe returned,
+/// with how the flow stands at that point. With several interceptors,
+/// `before` runs in the order they were registered and `after` in the
+/// reverse order. Skipped pipes are not intercepted.
+///
+/// An interceptor can be registered for the whole process with
+/// `register_interceptor`, on a `PipelineBuilder`, a `PipelineDef` or a
+/// single `Pipeline`.
+///
+/// ```rust
+///# use fama::{Interceptor, PipeContent, PipeInfo, Pipeline};
+/// struct RequireAdmin;
+///
+/// #[fama::async_trait]
+/// impl Interceptor for RequireAdmin {
+///     async fn before(&self, pipe: &PipeInfo, content: &PipeContent) {
+///         let is_admin = content.container().get_type::<bool>().await.unwrap_or_default();
+///         if pipe.tags.iter().any(|tag| tag == "admin") && !is_admin {
+///             content.stop_the_flow().await;
+///         }
+///     }
+/// }
+///
+///# #[tokio::main]
+///# async fn main() {
+/// let pipeline = Pipeline::pass(false)
+///     .await
+///     .intercept(RequireAdmin)
+///     .tag("admin")
+///     .through_fn(|| async { println!("deleting the account") })
+///     .await;
+///
+/// assert!(pipeline.outcome().is_stopped());
+///# }
+/// ```
+#[async_trait]
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called before the pipe
+    async fn before(&self, _pipe: &PipeInfo, _content: &PipeContent) {}
+
+    /// Called after the pipe
+    async fn after(&self, _pipe: &PipeInfo, _content: &PipeContent, _outcome: &FlowOutcome) {}
+}
+
+/// Intercepts the pipes of every pipeline in the process
+pub fn register_interceptor<I: Interceptor>(interceptor: I) {
+    INTERCEPTORS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Arc::new(interceptor));
+}
+
+/// Returns the process wide interceptors followed by `local`
+pub(crate) fn interceptors(local: &[Arc<dyn Interceptor>]) -> Vec<Arc<dyn Interceptor>> {
+    let global = INTERCEPTORS.read().unwrap_or_else(PoisonError::into_inner);
+    global.iter().chain(local).cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{PipeStatus, PipelineBuilder, PipelineTrait};
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Audit(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Interceptor for Audit {
+        async fn before(&self, pipe: &PipeInfo, _: &PipeContent) {
+            let name = pipe.name.clone().unwrap_or_default();
+            self.0.lock().unwrap().push(format!("before {}", name));
+        }
+
+        async fn after(&self, pipe: &PipeInfo, _: &PipeContent, outcome: &FlowOutcome) {
+            let name = pipe.name.clone().unwrap_or_default();
+            let state = if outcome.is_completed() {
+                "ok"
+            } else {
+                "ended"
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("after {} {}", name, state));
+        }
+    }
+
+    struct Label(&'static str, Audit);
+
+    #[async_trait]
+    impl Interceptor for Label {
+        async fn before(&self, _: &PipeInfo, _: &PipeContent) {
+            self.1.0.lock().unwrap().push(format!("{} in", self.0));
+        }
+
+        async fn after(&self, _: &PipeInfo, _: &PipeContent, _: &FlowOutcome) {
+            self.1.0.lock().unwrap().push(format!("{} out", self.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_interceptors() {
+        let audit = Audit::default();
+        let builder = PipelineBuilder::<i128>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .named("double")
+                        .store_fn(|num: i128| async move { num * 2 })
+                        .await
+                        .named("check")
+                        .next_fn(|num: i128| async move { num < 10 })
+                        .await
+                        .named("never")
+                        .through_fn(|| async {})
+                        .await
+                })
+            })
+            .await;
+        builder.intercept(audit.clone()).await;
+
+        assert!(!builder.confirm(6).await);
+        assert_eq!(
+            *audit.0.lock().unwrap(),
+            [
+                "before double",
+                "after double ok",
+                "before check",
+                "after check ended"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_order_and_stop_in_before() {
+        struct Deny;
+        #[async_trait]
+        impl Interceptor for Deny {
+            async fn before(&self, pipe: &PipeInfo, content: &PipeContent) {
+                if pipe.index == 1 {
+                    content.stop_the_flow().await;
+                }
+            }
+        }
+
+        let audit = Audit::default();
+        let pipeline = crate::Pipeline::pass(1_i128)
+            .await
+            .intercept(Label("outer", audit.clone()))
+            .intercept(Label("inner", audit.clone()))
+            .intercept(Deny)
+            .store_fn(|num: i128| async move { num + 1 })
+            .await
+            .store_fn(|num: i128| async move { num + 1 })
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 2);
+        assert_eq!(pipeline.outcome().at_pipe(), Some(1));
+        assert_eq!(
+            audit.0.lock().unwrap()[..4],
+            ["outer in", "inner in", "inner out", "outer out"]
+        );
+        assert_eq!(pipeline.execution_log()[1].status, PipeStatus::Skipped);
+        assert_eq!(pipeline.execution_log().ran().count(), 1);
+    }
+}
@@ -0,0 +1,442 @@
+use std::{any::TypeId, collections::HashMap, fmt::Display, sync::Arc};
+
+use crate::{
+    FamaPipe, FlowOutcome, PipeContent, Pipeline, PipelineDef, Reason,
+    content::StoredEntry,
+    pipeline::{Flow, PipeFnHandler, Settings},
+};
+
+type Merger = Arc<dyn Fn(&[StoredEntry]) -> StoredEntry + Send + Sync>;
+
+/// How values of the same type, stored by more than one concurrent
+/// branch, are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the value that was stored last
+    #[default]
+    LastWriterWins,
+    /// Fail the flow with a `StoreConflict` error
+    ErrorOnConflict,
+}
+
+/// The error a flow fails with when concurrent branches stored
+/// the same type and `MergeStrategy::ErrorOnConflict` is used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreConflict {
+    pub type_name: &'static str,
+}
+
+impl Display for StoreConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "more than one concurrent pipe stored a `{}`",
+            self.type_name
+        )
+    }
+}
+
+impl std::error::Error for StoreConflict {}
+
+/// A set of branches that run concurrently. See `Pipeline::through_all`
+///
+/// Each branch gets its own copy of the values stored so far. Once all
+/// the branches are done, the values they stored are copied back into
+/// the pipeline.
+///
+/// ```rust
+///# use fama::{Join, MergeStrategy, Pipeline};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct Tags(Vec<&'static str>);
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let tags = Pipeline::pass(10)
+///         .await
+///         .through_all(
+///             Join::new()
+///                 .store_fn(|| async { Tags(vec!["new"]) })
+///                 .store_fn(|num: i32| async move { Tags(vec![if num > 5 { "big" } else { "small" }]) })
+///                 .merge(|mut a: Tags, b: Tags| {
+///                     a.0.extend(b.0);
+///                     a
+///                 }),
+///         )
+///         .await
+///         .deliver_as::<Tags>()
+///         .await;
+///
+///     assert_eq!(tags.0.len(), 2);
+/// }
+/// ```
+pub struct Join<T: Clone + Send + Sync + 'static> {
+    branches: Vec<PipelineDef<T>>,
+    strategy: MergeStrategy,
+    mergers: HashMap<TypeId, Merger>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Join<T> {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+            strategy: MergeStrategy::default(),
+            mergers: HashMap::new(),
+        }
+    }
+
+    /// Adds a branch made of one or more pipes
+    pub fn branch(mut self, def: PipelineDef<T>) -> Self {
+        self.branches.push(def);
+        self
+    }
+
+    /// Sets how conflicting stores are handled. Types with
+    /// a `merge` function are not affected
+    pub fn on_conflict(mut self, strategy: MergeStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Combines the values of type `U` stored by the branches,
+    /// in the order they were stored
+    pub fn merge<U: Clone + Send + Sync + 'static>(
+        mut self,
+        merge: impl Fn(U, U) -> U + Send + Sync + 'static,
+    ) -> Self {
+        self.mergers.insert(
+            TypeId::of::<U>(),
+            Arc::new(move |entries| {
+                let mut values = entries
+                    .iter()
+                    .filter_map(|entry| entry.value.as_any().downcast_ref::<U>().cloned());
+                let first = values.next().expect("at least one value to merge");
+                StoredEntry::new(values.fold(first, &merge))
+            }),
+        );
+        self
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::through_fn`
+    pub fn through_fn<H, Args, O>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().through_fn(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::next_fn`
+    pub fn next_fn<H, Args>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().next_fn(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::store_fn`
+    pub fn store_fn<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().store_fn(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::some_fn`
+    pub fn some_fn<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Option<O>>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().some_fn(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::ok_fn`
+    pub fn ok_fn<H, Args, O, E>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<O, E>>,
+        Args: busybody::Resolver + Send + 'static,
+        O: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        self.branch(PipelineDef::new().ok_fn(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::through`
+    pub fn through<H, Args, O>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+        O: Send + 'static,
+    {
+        self.branch(PipelineDef::new().through(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::next`
+    pub fn next<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().next(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::store`
+    pub fn store<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().store(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::some`
+    pub fn some<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Option<O>> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        self.branch(PipelineDef::new().some(handler))
+    }
+
+    /// Adds a single pipe branch. See `Pipeline::ok`
+    pub fn ok<H, Args, O, E>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Result<O, E>> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+        O: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        self.branch(PipelineDef::new().ok(handler))
+    }
+
+    /// Runs the branches against copies of `parent`, copies the values
+    /// they stored back and returns how the flow should carry on
+    pub(crate) async fn run(&self, parent: PipeContent, settings: Settings) -> Flow {
+        let mut forks = Vec::with_capacity(self.branches.len());
+        for _ in &self.branches {
+            forks.push(parent.fork().await);
+        }
+        let since = PipeContent::write_sequence();
+
+        let mut branches =
+            futures::future::join_all(self.branches.iter().zip(forks).map(|(def, content)| {
+                let settings = settings.clone();
+                async move {
+                    def.run_on(Pipeline::from_content(content, settings).await)
+                        .await
+                }
+            }))
+            .await;
+
+        // Branches that completed are undone too when the flow fails later
+        for branch in &mut branches {
+            branch.hand_up_compensations(&parent);
+        }
+
+        let mut written: HashMap<TypeId, Vec<StoredEntry>> = HashMap::new();
+        for branch in &branches {
+            for entry in branch.pipe_content().stored_since(since) {
+                written.entry(entry.type_id).or_default().push(entry);
+            }
+        }
+
+        let mut merged = Vec::with_capacity(written.len());
+        for (type_id, mut entries) in written {
+            entries.sort_by_key(|entry| entry.seq);
+            let entry = match self.mergers.get(&type_id) {
+                Some(merge) => merge(&entries),
+                None if entries.len() > 1 && self.strategy == MergeStrategy::ErrorOnConflict => {
+                    return Flow::Fail(Reason::new(StoreConflict {
+                        type_name: entries[0].value.type_name(),
+                    }));
+                }
+                None => entries.pop().expect("at least one stored value"),
+            };
+            merged.push(entry);
+        }
+
+        merged.sort_by_key(|entry| entry.seq);
+        for entry in merged {
+            entry.value.store_into(&parent).await;
+        }
+
+        branches
+            .iter()
+            .map(|branch| Flow::from(branch.outcome()))
+            .find(|flow| !matches!(flow, Flow::Continue))
+            .unwrap_or(Flow::Continue)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Clone for Join<T> {
+    fn clone(&self) -> Self {
+        Self {
+            branches: self.branches.clone(),
+            strategy: self.strategy,
+            mergers: self.mergers.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for Join<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&FlowOutcome> for Flow {
+    fn from(outcome: &FlowOutcome) -> Self {
+        match outcome {
+            FlowOutcome::Completed => Flow::Continue,
+            FlowOutcome::Stopped { reason, .. } => Flow::Stop(reason.clone()),
+            FlowOutcome::Failed { error, .. } => Flow::Fail(error.clone()),
+            FlowOutcome::TimedOut { .. } => Flow::TimeOut,
+            FlowOutcome::Cancelled { .. } => Flow::Cancel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::StopReason;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_branches_run_concurrently() {
+        // Each branch waits for the other one, which only
+        // returns when both are running at the same time
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let (first, second) = (barrier.clone(), barrier);
+        let run = Pipeline::pass(1).await.through_all(
+            Join::new()
+                .through_fn(move || {
+                    let barrier = first.clone();
+                    async move { barrier.wait().await }
+                })
+                .through_fn(move || {
+                    let barrier = second.clone();
+                    async move { barrier.wait().await }
+                })
+                .store_fn(|num: i32| async move { num.to_string() }),
+        );
+
+        let pipeline = tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("the branches did not run concurrently");
+        assert!(pipeline.confirm());
+        assert_eq!(pipeline.deliver_as::<String>().await, "1");
+    }
+
+    #[tokio::test]
+    async fn test_completed_branches_are_compensated() {
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let branch_undone = undone.clone();
+        let pipeline = Pipeline::pass(1)
+            .await
+            .through_all(
+                Join::new()
+                    .branch(
+                        PipelineDef::new()
+                            .store_fn(|num: i32| async move { num * 10 })
+                            .compensate_fn(move |num: i32| {
+                                let undone = branch_undone.clone();
+                                async move {
+                                    undone.lock().unwrap().push(num);
+                                    Ok::<(), String>(())
+                                }
+                            }),
+                    )
+                    .next_fn(|| async { false }),
+            )
+            .await;
+
+        assert!(undone.lock().unwrap().is_empty());
+        let pipeline = pipeline.finish().await;
+        assert_eq!(*undone.lock().unwrap(), vec![10]);
+        assert_eq!(pipeline.compensations()[0].pipe, 0);
+    }
+
+    #[tokio::test]
+    async fn test_branch_stops_the_flow() {
+        let pipeline = Pipeline::pass(1)
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await
+            .through_all(
+                Join::new()
+                    .store_fn(|num: i32| async move { num * 10 })
+                    .next_fn(|| async { false }),
+            )
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await;
+
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 1,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
+        assert_eq!(pipeline.deliver().await, 20);
+    }
+
+    #[tokio::test]
+    async fn test_last_writer_wins() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .through_all(
+                Join::new()
+                    .store_fn(|| async {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        "slow"
+                    })
+                    .store_fn(|| async { "fast" }),
+            )
+            .await;
+
+        assert_eq!(pipeline.deliver_as::<&str>().await, "slow");
+    }
+
+    #[tokio::test]
+    async fn test_error_on_conflict() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .through_all(
+                Join::new()
+                    .on_conflict(MergeStrategy::ErrorOnConflict)
+                    .store_fn(|| async { 1 })
+                    .store_fn(|| async { 2 }),
+            )
+            .await;
+
+        match pipeline.outcome() {
+            FlowOutcome::Failed { error, .. } => {
+                assert_eq!(
+                    error.downcast_ref::<StoreConflict>(),
+                    Some(&StoreConflict { type_name: "i32" })
+                );
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert_eq!(pipeline.deliver().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        let def = PipelineDef::new().through_all(
+            Join::new()
+                .on_conflict(MergeStrategy::ErrorOnConflict)
+                .store_fn(|num: i32| async move { num + 1 })
+                .store_fn(|num: i32| async move { num + 2 })
+                .merge(|a: i32, b: i32| a + b),
+        );
+
+        assert_eq!(def.run(1).await.deliver().await, 5);
+        assert_eq!(def.run(10).await.deliver().await, 23);
+    }
+}
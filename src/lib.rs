@@ -145,11 +145,48 @@
 //! }
 //! ```
 //!
+mod cancel;
+mod compensation;
 mod content;
+mod execution_log;
+mod interceptor;
+mod join;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod outcome;
 mod pipeline;
 mod pipeline_builder;
+mod pipeline_def;
+mod report;
+mod retry;
+mod stream;
+mod trace;
+mod try_pipeline;
+mod worker;
 
+pub use cancel::CancellationToken;
+pub use compensation::Compensation;
 pub use content::PipeContent;
+pub use execution_log::ExecutionLog;
+pub use execution_log::PipeRecord;
+pub use execution_log::PipeStatus;
+pub use interceptor::Interceptor;
+pub use interceptor::PipeInfo;
+pub use interceptor::register_interceptor;
+pub use join::Join;
+pub use join::MergeStrategy;
+pub use join::StoreConflict;
+#[cfg(feature = "metrics")]
+pub use metrics::{
+    Histogram, InMemoryRecorder, MetricOutcome, Metrics, MetricsRecorder, set_metrics_recorder,
+};
+pub use outcome::CaseKeyMismatch;
+pub use outcome::Delivered;
+pub use outcome::FlowOutcome;
+pub use outcome::IterationLimit;
+pub use outcome::PipePanic;
+pub use outcome::Reason;
+pub use outcome::StopReason;
 pub use pipeline::FamaPipe;
 pub use pipeline::Pipeline;
 
@@ -157,6 +194,17 @@ pub use async_trait::async_trait;
 pub use busybody;
 pub use pipeline_builder::PipelineBuilder;
 pub use pipeline_builder::PipelineBuilderTrait;
+pub use pipeline_def::PipelineDef;
+pub use report::PipelineReport;
+pub use retry::Backoff;
+pub use retry::RetryPolicy;
+pub use stream::StreamOptions;
+pub use try_pipeline::TryPipeline;
+pub use worker::PipelineWorker;
+pub use worker::WorkerClosed;
+pub use worker::WorkerQueue;
+
+use futures::StreamExt;
 
 #[async_trait::async_trait]
 pub trait PipelineTrait {
@@ -164,22 +212,26 @@ pub trait PipelineTrait {
 
     async fn handle_pipe(&self, pipeline: Pipeline<Self::Content>) -> Pipeline<Self::Content>;
 
-    async fn deliver(&self, subject: Self::Content) -> Self::Content {
+    /// Passes the subject through the pipes and returns the pipeline
+    /// for further inspection
+    async fn process(&self, subject: Self::Content) -> Pipeline<Self::Content> {
         let pipeline = Pipeline::pass(subject).await;
-        self.handle_pipe(pipeline).await.deliver().await
+        self.handle_pipe(pipeline).await.finish().await
+    }
+
+    async fn deliver(&self, subject: Self::Content) -> Self::Content {
+        self.process(subject).await.deliver().await
     }
 
     async fn try_to_deliver(&self, subject: Self::Content) -> Option<Self::Content> {
-        let pipeline = Pipeline::pass(subject).await;
-        self.handle_pipe(pipeline).await.try_deliver_as().await
+        self.process(subject).await.try_to_deliver().await
     }
 
     async fn deliver_as<R: Clone + Send + Sync + 'static>(&self, subject: Self::Content) -> R
     where
         Self: Sized,
     {
-        let pipeline = Pipeline::pass(subject).await;
-        self.handle_pipe(pipeline).await.deliver_as().await
+        self.process(subject).await.deliver_as().await
     }
 
     async fn try_deliver_as<R: Clone + Send + Sync + 'static>(
@@ -189,12 +241,90 @@ pub trait PipelineTrait {
     where
         Self: Sized,
     {
-        let pipeline = Pipeline::pass(subject).await;
-        self.handle_pipe(pipeline).await.try_deliver_as().await
+        self.process(subject).await.try_deliver_as().await
+    }
+
+    /// Returns the delivered subject, or the error the flow ended with.
+    /// See `Pipeline::deliver_result`
+    async fn deliver_result<E>(&self, subject: Self::Content) -> Result<Self::Content, E>
+    where
+        Self: Sized,
+        E: From<FlowOutcome> + Clone + Send + 'static,
+    {
+        self.process(subject).await.deliver_result().await
     }
 
     async fn confirm(&self, subject: Self::Content) -> bool {
-        let pipeline = Pipeline::pass(subject).await;
-        self.handle_pipe(pipeline).await.confirm()
+        self.process(subject).await.confirm()
+    }
+
+    /// Returns the execution log of the subject's trip through the pipes
+    async fn execution_log(&self, subject: Self::Content) -> ExecutionLog {
+        self.process(subject).await.execution_log().clone()
+    }
+
+    /// Returns the timings of the subject's trip through the pipes.
+    /// See `PipelineReport`
+    async fn report(&self, subject: Self::Content) -> PipelineReport {
+        self.process(subject).await.report()
+    }
+
+    /// Passes each subject through the pipes, with up to `concurrency`
+    /// subjects at the same time. The pipelines are returned in the order
+    /// of the subjects
+    async fn process_many(
+        &self,
+        subjects: Vec<Self::Content>,
+        concurrency: usize,
+    ) -> Vec<Pipeline<Self::Content>> {
+        futures::stream::iter(subjects)
+            .map(|subject| self.process(subject))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Delivers each subject along with how its flow ended.
+    /// See `PipelineTrait::process_many`
+    async fn deliver_many(
+        &self,
+        subjects: Vec<Self::Content>,
+        concurrency: usize,
+    ) -> Vec<Delivered<Self::Content>> {
+        let mut delivered = Vec::with_capacity(subjects.len());
+        for pipeline in self.process_many(subjects, concurrency).await {
+            delivered.push(Delivered {
+                content: pipeline.deliver().await,
+                outcome: pipeline.outcome().clone(),
+            });
+        }
+
+        delivered
+    }
+
+    /// Like `deliver_many`, without panicking when a content is missing
+    async fn try_deliver_many(
+        &self,
+        subjects: Vec<Self::Content>,
+        concurrency: usize,
+    ) -> Vec<Delivered<Option<Self::Content>>> {
+        let mut delivered = Vec::with_capacity(subjects.len());
+        for pipeline in self.process_many(subjects, concurrency).await {
+            delivered.push(Delivered {
+                content: pipeline.try_to_deliver().await,
+                outcome: pipeline.outcome().clone(),
+            });
+        }
+
+        delivered
+    }
+
+    /// Returns, for each subject, whether it went through all the pipes
+    async fn confirm_many(&self, subjects: Vec<Self::Content>, concurrency: usize) -> Vec<bool> {
+        self.process_many(subjects, concurrency)
+            .await
+            .iter()
+            .map(Pipeline::confirm)
+            .collect()
     }
 }
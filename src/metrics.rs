@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::Duration,
+};
+
+use crate::FlowOutcome;
+
+static RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+
+/// How a pipe or a pipeline run ended, as far as metrics are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricOutcome {
+    Completed,
+    /// The flow was stopped or cancelled
+    Stopped,
+    /// A pipe returned an error, panicked or ran out of time
+    Failed,
+}
+
+impl From<&FlowOutcome> for MetricOutcome {
+    fn from(outcome: &FlowOutcome) -> Self {
+        match outcome {
+            FlowOutcome::Completed => Self::Completed,
+            FlowOutcome::Stopped { .. } | FlowOutcome::Cancelled { .. } => Self::Stopped,
+            FlowOutcome::Failed { .. } | FlowOutcome::TimedOut { .. } => Self::Failed,
+        }
+    }
+}
+
+/// Receives a measurement each time a pipe or a pipeline runs
+///
+/// Pipelines are identified by their content type name, or by the name
+/// given with `Pipeline::with_name`. Pipes are identified by their name.
+/// See `set_metrics_recorder`
+pub trait MetricsRecorder: Send + Sync + 'static {
+    /// Called after a pipe was called. Skipped pipes are not recorded
+    fn record_pipe(&self, pipeline: &str, pipe: &str, outcome: MetricOutcome, latency: Duration);
+
+    /// Called when a pipeline run is over: when `Pipeline::finish` is
+    /// called, which `PipelineTrait`, `PipelineDef` and `PipelineWorker`
+    /// do for the pipelines they run
+    fn record_pipeline(&self, pipeline: &str, outcome: MetricOutcome, latency: Duration);
+}
+
+/// Sends the measurements of every pipeline in the process to `recorder`,
+/// replacing the previous recorder
+pub fn set_metrics_recorder<R: MetricsRecorder>(recorder: R) {
+    *RECORDER.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(recorder));
+}
+
+fn recorder() -> Option<Arc<dyn MetricsRecorder>> {
+    RECORDER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+pub(crate) fn record_pipe(pipeline: &str, pipe: &str, outcome: MetricOutcome, latency: Duration) {
+    if let Some(recorder) = recorder() {
+        recorder.record_pipe(pipeline, pipe, outcome, latency);
+    }
+}
+
+pub(crate) fn record_pipeline(pipeline: &str, outcome: MetricOutcome, latency: Duration) {
+    if let Some(recorder) = recorder() {
+        recorder.record_pipeline(pipeline, outcome, latency);
+    }
+}
+
+/// Counts the latencies that fall under each bound
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: Vec<Duration>,
+    counts: Vec<u64>,
+    sum: Duration,
+}
+
+impl Histogram {
+    /// Creates a histogram with one bucket per bound, plus one for the
+    /// latencies above the largest bound
+    pub fn new(mut bounds: Vec<Duration>) -> Self {
+        bounds.sort();
+        Self {
+            counts: vec![0; bounds.len() + 1],
+            bounds,
+            sum: Duration::ZERO,
+        }
+    }
+
+    pub fn observe(&mut self, latency: Duration) {
+        let bucket = self.bounds.partition_point(|bound| *bound < latency);
+        self.counts[bucket] += 1;
+        self.sum += latency;
+    }
+
+    /// The upper bounds of the buckets
+    pub fn bounds(&self) -> &[Duration] {
+        &self.bounds
+    }
+
+    /// The number of latencies in each bucket. The last bucket holds
+    /// the latencies above the largest bound
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+}
+
+impl Default for Histogram {
+    /// Buckets from one millisecond to ten seconds
+    fn default() -> Self {
+        Self::new(
+            [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000]
+                .into_iter()
+                .map(Duration::from_millis)
+                .collect(),
+        )
+    }
+}
+
+/// The measurements of a pipe or a pipeline
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    pub invocations: u64,
+    pub completions: u64,
+    pub stops: u64,
+    pub failures: u64,
+    pub latency: Histogram,
+}
+
+impl Metrics {
+    fn observe(&mut self, outcome: MetricOutcome, latency: Duration) {
+        self.invocations += 1;
+        match outcome {
+            MetricOutcome::Completed => self.completions += 1,
+            MetricOutcome::Stopped => self.stops += 1,
+            MetricOutcome::Failed => self.failures += 1,
+        }
+        self.latency.observe(latency);
+    }
+}
+
+/// A `MetricsRecorder` that keeps the measurements in memory.
+/// Clones share the same measurements
+///
+/// ```rust
+///# use fama::{InMemoryRecorder, PipelineDef, PipelineTrait, set_metrics_recorder};
+///# #[tokio::main]
+///# async fn main() {
+/// let recorder = InMemoryRecorder::new();
+/// set_metrics_recorder(recorder.clone());
+///
+/// let def = PipelineDef::<i64>::new()
+///     .named("positive")
+///     .next_fn(|num: i64| async move { num > 0 });
+/// def.confirm(1).await;
+/// def.confirm(-1).await;
+///
+/// let pipeline = recorder.pipeline("i64").unwrap();
+/// assert_eq!((pipeline.invocations, pipeline.stops), (2, 1));
+/// assert_eq!(recorder.pipe("i64", "positive").unwrap().completions, 1);
+///# }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRecorder {
+    pipelines: Arc<Mutex<HashMap<String, Metrics>>>,
+    pipes: Arc<Mutex<HashMap<(String, String), Metrics>>>,
+}
+
+impl InMemoryRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the measurements of the pipeline runs
+    pub fn pipeline(&self, pipeline: &str) -> Option<Metrics> {
+        self.pipelines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(pipeline)
+            .cloned()
+    }
+
+    /// Returns the measurements of a pipe of the pipeline
+    pub fn pipe(&self, pipeline: &str, pipe: &str) -> Option<Metrics> {
+        self.pipes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&(pipeline.to_string(), pipe.to_string()))
+            .cloned()
+    }
+}
+
+impl MetricsRecorder for InMemoryRecorder {
+    fn record_pipe(&self, pipeline: &str, pipe: &str, outcome: MetricOutcome, latency: Duration) {
+        self.pipes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((pipeline.to_string(), pipe.to_string()))
+            .or_default()
+            .observe(outcome, latency);
+    }
+
+    fn record_pipeline(&self, pipeline: &str, outcome: MetricOutcome, latency: Duration) {
+        self.pipelines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(pipeline.to_string())
+            .or_default()
+            .observe(outcome, latency);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Pipeline, PipelineBuilder, PipelineTrait};
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram =
+            Histogram::new(vec![Duration::from_millis(10), Duration::from_millis(1)]);
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(1));
+        histogram.observe(Duration::from_millis(7));
+        histogram.observe(Duration::from_secs(1));
+
+        assert_eq!(histogram.bounds()[0], Duration::from_millis(1));
+        assert_eq!(histogram.counts(), [2, 1, 1]);
+        assert_eq!(histogram.count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_recorder() {
+        let recorder = InMemoryRecorder::new();
+        set_metrics_recorder(recorder.clone());
+
+        let builder = PipelineBuilder::<u128>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .named("parse")
+                        .ok_fn(|num: u128| async move {
+                            if num == 0 {
+                                return Err("zero".to_string());
+                            }
+                            Ok(num)
+                        })
+                        .await
+                        .named("is-even")
+                        .next_fn(|num: u128| async move { num.is_multiple_of(2) })
+                        .await
+                })
+            })
+            .await;
+
+        builder.confirm_many(vec![0, 1, 2, 4], 2).await;
+
+        let pipeline = recorder.pipeline("u128").unwrap();
+        assert_eq!(pipeline.invocations, 4);
+        assert_eq!(
+            (pipeline.completions, pipeline.stops, pipeline.failures),
+            (2, 1, 1)
+        );
+        assert_eq!(pipeline.latency.count(), 4);
+
+        let parse = recorder.pipe("u128", "parse").unwrap();
+        assert_eq!((parse.invocations, parse.failures), (4, 1));
+        let is_even = recorder.pipe("u128", "is-even").unwrap();
+        assert_eq!((is_even.invocations, is_even.stops), (3, 1));
+
+        let pipeline = Pipeline::pass(2_u128)
+            .await
+            .with_name("checkout")
+            .named("is-even")
+            .next_fn(|num: u128| async move { num.is_multiple_of(2) })
+            .await;
+        assert_eq!(recorder.pipe("checkout", "is-even").unwrap().completions, 1);
+        assert!(recorder.pipeline("checkout").is_none());
+
+        pipeline.finish().await;
+        assert_eq!(recorder.pipeline("checkout").unwrap().completions, 1);
+    }
+}
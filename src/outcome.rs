@@ -0,0 +1,274 @@
+use std::{
+    any::{Any, type_name},
+    fmt::{Debug, Display},
+    sync::Arc,
+};
+
+/// How the flow ended
+#[derive(Debug, Clone, Default)]
+pub enum FlowOutcome {
+    /// The content went through all the pipes
+    #[default]
+    Completed,
+    /// A pipe stopped the flow
+    Stopped { at_pipe: usize, reason: StopReason },
+    /// A pipe returned an error
+    Failed { at_pipe: usize, error: Reason },
+    /// A pipe ran out of time
+    TimedOut { at_pipe: usize },
+    /// The cancellation token was cancelled before the pipe ran
+    Cancelled { at_pipe: usize },
+}
+
+impl FlowOutcome {
+    pub fn is_completed(&self) -> bool {
+        matches!(self, Self::Completed)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, Self::Stopped { .. })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed { .. })
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self, Self::TimedOut { .. })
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Cancelled { .. })
+    }
+
+    /// Returns the index of the pipe that ended the flow
+    pub fn at_pipe(&self) -> Option<usize> {
+        match self {
+            Self::Completed => None,
+            Self::Stopped { at_pipe, .. }
+            | Self::Failed { at_pipe, .. }
+            | Self::TimedOut { at_pipe }
+            | Self::Cancelled { at_pipe } => Some(*at_pipe),
+        }
+    }
+}
+
+/// The delivered content of one subject of a batch and how its flow ended
+#[derive(Debug, Clone)]
+pub struct Delivered<R> {
+    pub content: R,
+    pub outcome: FlowOutcome,
+}
+
+/// Why a pipe stopped the flow
+#[derive(Debug, Clone, Default)]
+pub enum StopReason {
+    /// `PipeContent::stop_the_flow` was called
+    #[default]
+    Unspecified,
+    /// A `next` or `next_fn` pipe returned `false`
+    ReturnedFalse,
+    /// A `some` or `some_fn` pipe returned `None`
+    ReturnedNone,
+    /// The reason given to `PipeContent::stop_the_flow_with`
+    Custom(Reason),
+}
+
+impl StopReason {
+    /// Returns the custom reason if there is one
+    pub fn custom(&self) -> Option<&Reason> {
+        match self {
+            Self::Custom(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unspecified => write!(f, "the flow was stopped"),
+            Self::ReturnedFalse => write!(f, "the pipe returned false"),
+            Self::ReturnedNone => write!(f, "the pipe returned none"),
+            Self::Custom(reason) => Display::fmt(reason, f),
+        }
+    }
+}
+
+/// A type erased stop reason or error value
+///
+/// The original value can be retrieved with `downcast_ref`
+#[derive(Clone)]
+pub struct Reason {
+    value: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+}
+
+impl Reason {
+    pub fn new<R: Send + Sync + 'static>(value: R) -> Self {
+        Self {
+            value: Arc::new(value),
+            type_name: type_name::<R>(),
+        }
+    }
+
+    /// Returns a reference to the value if it is of type `R`
+    pub fn downcast_ref<R: 'static>(&self) -> Option<&R> {
+        self.value.downcast_ref()
+    }
+
+    /// Returns true if the value is of type `R`
+    pub fn is<R: 'static>(&self) -> bool {
+        self.value.is::<R>()
+    }
+
+    /// Returns the value when it is a `String` or a `&'static str`
+    pub fn as_str(&self) -> Option<&str> {
+        self.downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| self.downcast_ref::<&'static str>().copied())
+    }
+
+    /// The type name of the value
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl Debug for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.as_str() {
+            Some(message) => write!(f, "Reason({:?})", message),
+            None => write!(f, "Reason({})", self.type_name),
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str().unwrap_or(self.type_name))
+    }
+}
+
+/// A panic caught while a pipe was running
+///
+/// It is stored as the error of a `FlowOutcome::Failed`
+#[derive(Debug, Clone)]
+pub struct PipePanic {
+    /// The position of the pipe that panicked
+    pub index: usize,
+    /// The pipe name, when the pipe has one
+    pub name: Option<String>,
+    /// The panic message
+    pub message: String,
+}
+
+impl PipePanic {
+    pub(crate) fn new(index: usize, name: Option<String>, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload
+                .downcast_ref::<&'static str>()
+                .map(|message| message.to_string())
+                .unwrap_or_else(|| "unknown panic".to_string()),
+        };
+
+        Self {
+            index,
+            name,
+            message,
+        }
+    }
+}
+
+impl Display for PipePanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(
+                f,
+                "pipe {} ({}) panicked: {}",
+                self.index, name, self.message
+            ),
+            None => write!(f, "pipe {} panicked: {}", self.index, self.message),
+        }
+    }
+}
+
+impl std::error::Error for PipePanic {}
+
+/// A `repeat_while_fn` condition was still true after the maximum
+/// number of iterations
+///
+/// It is stored as the error of a `FlowOutcome::Failed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IterationLimit {
+    pub max_iterations: usize,
+}
+
+impl Display for IterationLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the loop did not end after {} iterations",
+            self.max_iterations
+        )
+    }
+}
+
+impl std::error::Error for IterationLimit {}
+
+/// A `case` key is not of the type returned by the `switch_fn` selector,
+/// so it could never match
+///
+/// It is stored as the error of a `FlowOutcome::Failed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseKeyMismatch {
+    /// The type returned by the selector
+    pub selected: &'static str,
+    /// The type of the `case` key
+    pub case: &'static str,
+}
+
+impl Display for CaseKeyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "a case key of type {} cannot match a selected {}",
+            self.case, self.selected
+        )
+    }
+}
+
+impl std::error::Error for CaseKeyMismatch {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum SignupError {
+        UsernameTaken,
+    }
+
+    #[test]
+    fn test_reason_downcast() {
+        let reason = Reason::new(SignupError::UsernameTaken);
+
+        assert!(reason.is::<SignupError>());
+        assert_eq!(
+            reason.downcast_ref::<SignupError>(),
+            Some(&SignupError::UsernameTaken)
+        );
+        assert!(reason.as_str().is_none());
+        assert!(reason.to_string().ends_with("SignupError"));
+    }
+
+    #[test]
+    fn test_reason_message() {
+        assert_eq!(Reason::new("invalid email").as_str(), Some("invalid email"));
+        assert_eq!(
+            Reason::new("invalid email".to_string()).to_string(),
+            "invalid email"
+        );
+    }
+}
@@ -1,16 +1,101 @@
 use async_trait::async_trait;
 use busybody::Resolver;
-use futures::future::Future;
-use std::marker::PhantomData;
-
-use crate::{PipeContent, content::PipeState};
+use futures::{
+    FutureExt, StreamExt,
+    future::{Future, Ready, ready},
+    stream::FuturesOrdered,
+};
+use std::{
+    any::{Any, TypeId, type_name},
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    CancellationToken, Interceptor, Join, PipeContent, PipelineBuilderTrait, PipelineDef,
+    PipelineReport, TryPipeline,
+    compensation::{Compensate, Compensation, compensate_with},
+    execution_log::{ExecutionLog, PipeRecord, PipeStatus},
+    interceptor::{self, PipeInfo},
+    outcome::{CaseKeyMismatch, FlowOutcome, IterationLimit, PipePanic, Reason, StopReason},
+    retry::RetryPolicy,
+    trace::{self, Span},
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, MetricOutcome};
 
 /// The pipes manager
 #[derive(Clone)]
 pub struct Pipeline<T: Send + Sync + 'static> {
     phantom: PhantomData<T>,
     pipe_content: PipeContent,
-    went_through: bool,
+    log: ExecutionLog,
+    outcome: FlowOutcome,
+    settings: Settings,
+    next_pipe: PipeOptions,
+    branch_taken: Option<bool>,
+    selected: Option<Selected>,
+    compensations: Vec<(usize, Compensate)>,
+    compensated: Vec<Compensation>,
+    span: Span,
+    started: Instant,
+    unmapped: Option<Unmapped>,
+    finished: bool,
+    name: std::borrow::Cow<'static, str>,
+}
+
+/// Set when the `map` pipe that produces the content did not run
+#[derive(Clone)]
+struct Unmapped {
+    /// A content stored after this point is a new one
+    since: u64,
+    /// How the flow stood when the pipe was skipped
+    outcome: FlowOutcome,
+}
+
+/// The key returned by the last `switch_fn` selector
+#[derive(Clone)]
+struct Selected {
+    /// The position of the `switch_fn` pipe
+    pipe: usize,
+    key: Arc<dyn Any + Send + Sync>,
+    key_type: &'static str,
+    matched: bool,
+}
+
+/// Options that apply to all the pipes that follow.
+/// Nested and concurrent pipelines inherit them
+#[derive(Clone, Default)]
+pub(crate) struct Settings {
+    catch_panics: bool,
+    deadline: Option<Instant>,
+    cancellation: CancellationToken,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+}
+
+/// Options that only apply to the next pipe
+#[derive(Clone, Default)]
+struct PipeOptions {
+    catch_panic: bool,
+    timeout: Option<Duration>,
+    retry: Option<RetryPolicy>,
+    concurrency: Option<usize>,
+    finalizer: bool,
+    name: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+}
+
+/// What the pipeline should do after a pipe returned
+pub(crate) enum Flow {
+    Continue,
+    Stop(StopReason),
+    Fail(Reason),
+    TimeOut,
+    Cancel,
 }
 
 impl<T: Clone + Send + Sync + 'static> Pipeline<T> {
@@ -18,16 +103,171 @@ impl<T: Clone + Send + Sync + 'static> Pipeline<T> {
     /// This is the beginning of the pipeline
     pub async fn pass(content: T) -> Self {
         let pipe_content = PipeContent::new(content).await;
+        Self::from_content(pipe_content, Settings::default()).await
+    }
+
+    pub(crate) async fn from_content(pipe_content: PipeContent, settings: Settings) -> Self {
+        pipe_content
+            .container()
+            .set_type(settings.cancellation.clone())
+            .await;
 
         Self {
             pipe_content,
             phantom: PhantomData,
-            went_through: false,
+            log: ExecutionLog::default(),
+            outcome: FlowOutcome::Completed,
+            settings,
+            next_pipe: PipeOptions::default(),
+            branch_taken: None,
+            selected: None,
+            compensations: Vec::new(),
+            compensated: Vec::new(),
+            span: trace::pipeline_span(type_name::<T>()),
+            started: Instant::now(),
+            unmapped: None,
+            finished: false,
+            name: type_name::<T>().into(),
         }
     }
 
+    /// Catches panics from all the pipes that follow.
+    /// A panicking pipe fails the flow instead of unwinding the task.
+    /// The panic is available via `Pipeline::panicked`
+    pub fn catch_panics(mut self) -> Self {
+        self.settings.catch_panics = true;
+        self
+    }
+
+    /// Catches a panic from the next pipe only.
+    /// See `Pipeline::catch_panics`
+    pub fn isolated(mut self) -> Self {
+        self.next_pipe.catch_panic = true;
+        self
+    }
+
+    /// Limits how long the next pipe may run.
+    /// When the time runs out the flow ends with `FlowOutcome::TimedOut`
+    pub fn timeout(mut self, duration: Duration) -> Self {
+        self.next_pipe.timeout = Some(duration);
+        self
+    }
+
+    /// Retries the next pipe when it returns an `Err`.
+    /// Only applies to `ok` and `ok_fn` pipes, and to the pipes of a
+    /// `TryPipeline`. A timeout set
+    /// for the pipe covers all the attempts
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.next_pipe.retry = Some(policy);
+        self
+    }
+
+    /// Lets the next `for_each_fn` run up to `limit` items at the same time.
+    /// The results keep the order of the items
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.next_pipe.concurrency = Some(limit.max(1));
+        self
+    }
+
+    /// Names the next pipe. The execution log, `PipePanic` and the
+    /// tracing spans refer to the pipe by this name. Without it, a
+    /// closure is named after its type and a struct pipe after
+    /// `FamaPipe::name`
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let pipeline = fama::Pipeline::pass(7)
+    ///     .await
+    ///     .named("generate-user-id")
+    ///     .describe("assigns the id of a new user")
+    ///     .tag("signup")
+    ///     .store_fn(|id: i32| async move { format!("user-{}", id) })
+    ///     .await;
+    ///
+    /// let record = &pipeline.execution_log()[0];
+    /// assert_eq!(record.name.as_deref(), Some("generate-user-id"));
+    /// assert_eq!(record.tags, ["signup"]);
+    ///# }
+    /// ```
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.next_pipe.name = Some(name.into());
+        self
+    }
+
+    /// Describes what the next pipe does. See `Pipeline::named`
+    pub fn describe(mut self, description: impl Into<String>) -> Self {
+        self.next_pipe.description = Some(description.into());
+        self
+    }
+
+    /// Tags the next pipe. Can be used more than once.
+    /// See `Pipeline::named`
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.next_pipe.tags.push(tag.into());
+        self
+    }
+
+    /// Runs `interceptor` around each pipe that follows. Nested and
+    /// concurrent pipelines inherit it. See `Interceptor`
+    pub fn intercept<I: Interceptor>(mut self, interceptor: I) -> Self {
+        self.settings.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Adds `interceptors` after the current ones.
+    /// Returns where they start, for `Pipeline::pop_interceptors`
+    pub(crate) fn push_interceptors(&mut self, interceptors: &[Arc<dyn Interceptor>]) -> usize {
+        let start = self.settings.interceptors.len();
+        self.settings.interceptors.extend_from_slice(interceptors);
+        start
+    }
+
+    /// Removes the `count` interceptors added at `start`
+    pub(crate) fn pop_interceptors(mut self, start: usize, count: usize) -> Self {
+        let end = (start + count).min(self.settings.interceptors.len());
+        self.settings.interceptors.drain(start.min(end)..end);
+        self
+    }
+
+    /// Names the pipeline. With the `metrics` feature, the metrics
+    /// identify the pipeline by this name instead of the content type
+    /// name. The run itself is recorded once the pipeline is finished,
+    /// see `Pipeline::finish`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into().into();
+        self
+    }
+
+    /// Returns the name given with `with_name`, or the content type name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets a deadline for the remaining pipes.
+    /// A pipe still running at the deadline, or any pipe reached
+    /// after it, ends the flow with `FlowOutcome::TimedOut`
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.settings.deadline = Some(deadline);
+        self
+    }
+
+    /// Uses `token` to cancel the remaining pipes. Pipes can take the
+    /// token as an argument. Nested and concurrent pipelines share it.
+    /// See `CancellationToken`
+    pub async fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.container().set_type(token.clone()).await;
+        self.settings.cancellation = token;
+        self
+    }
+
+    /// Returns the token that cancels this pipeline
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.settings.cancellation.clone()
+    }
+
     pub async fn pass_content(self, content: T) -> Self {
-        self.container().set_type(content).await;
+        self.pipe_content.store(content).await;
         self
     }
 
@@ -35,20 +275,14 @@ impl<T: Clone + Send + Sync + 'static> Pipeline<T> {
     /// The closure can accept zero or more arguments.
     /// Unlike a struct pipe, a closure does not have to use a tuple
     /// for multiple arguments. Arguments can be up to 17
-    pub async fn through_fn<H, Args, O>(mut self, mut handler: H) -> Self
+    pub async fn through_fn<H, Args, O>(self, mut handler: H) -> Self
     where
         H: PipeFnHandler<Args, O>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            handler.pipe_fn_handle(args).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_through)
+            .await
     }
 
     /// Accepts a closure or function as a pipe.
@@ -56,71 +290,37 @@ impl<T: Clone + Send + Sync + 'static> Pipeline<T> {
     /// Unlike a struct pipe, a closure does not have to use a tuple
     /// for multiple arguments. Arguments can be up to 17
     /// Closure must return a boolean. `False` will stop the pipe flow
-    pub async fn next_fn<H, Args>(mut self, mut handler: H) -> Self
+    pub async fn next_fn<H, Args>(self, mut handler: H) -> Self
     where
         H: PipeFnHandler<Args, bool>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            if !handler.pipe_fn_handle(args).await {
-                self.container().set(PipeState::Stop).await;
-            }
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_next)
+            .await
     }
 
     /// Stores the result from the pipe handler
-    pub async fn store_fn<H, Args, O: Clone + Send + Sync + 'static>(
-        mut self,
-        mut handler: H,
-    ) -> Self
+    pub async fn store_fn<H, Args, O: Clone + Send + Sync + 'static>(self, mut handler: H) -> Self
     where
         H: PipeFnHandler<Args, O>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            self.container()
-                .set_type(handler.pipe_fn_handle(args).await)
-                .await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_store)
+            .await
     }
 
     // Stores Option<T> returned by the handler
     // If option is `none` the pipe flow is stopped
-    pub async fn some_fn<H, Args, O: Clone + Send + Sync + 'static>(
-        mut self,
-        mut handler: H,
-    ) -> Self
+    pub async fn some_fn<H, Args, O: Clone + Send + Sync + 'static>(self, mut handler: H) -> Self
     where
         H: PipeFnHandler<Args, Option<O>>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            let option = handler.pipe_fn_handle(args).await;
-
-            if option.is_none() {
-                self.container().set(PipeState::Stop).await;
-            }
-
-            self.container().set_type(option).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_some)
+            .await
     }
 
     // Stores Result<T, E> returned by the handler
@@ -131,546 +331,2832 @@ impl<T: Clone + Send + Sync + 'static> Pipeline<T> {
         O: Clone + Send + Sync + 'static,
         E: Clone + Send + Sync + 'static,
     >(
-        mut self,
+        self,
         mut handler: H,
     ) -> Self
     where
         H: PipeFnHandler<Args, Result<O, E>>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            let result = handler.pipe_fn_handle(args).await;
-
-            if result.is_err() {
-                self.container().set(PipeState::Stop).await;
-            }
-
-            self.container().set_type(result).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.name_pipe::<H>()
+            .run_ok_pipe(|args| handler.pipe_fn_handle(args))
+            .await
     }
 
     /// Accepts an instance of a struct that implements `fama::FamaPipe`
     /// The returned result will be store for the next pipe handlers
-    pub async fn through<H, Args, O>(mut self, handler: H) -> Self
+    pub async fn through<H, Args, O>(self, handler: H) -> Self
     where
         H: FamaPipe<Args, O>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            handler.receive_pipe_content(args).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_through)
+            .await
     }
 
     /// Accepts an instance of a struct that implements `fama::FamaPipe`
     /// Must return a boolean. `False` will halt the flow
-    pub async fn next<H, Args>(mut self, handler: H) -> Self
+    pub async fn next<H, Args>(self, handler: H) -> Self
     where
         H: FamaPipe<Args, bool>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            if !handler.receive_pipe_content(args).await {
-                self.container().set(PipeState::Stop).await;
-            }
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_next)
+            .await
     }
-    pub async fn store<H, Args, O: Clone + Send + Sync + 'static>(mut self, handler: H) -> Self
+    pub async fn store<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
     where
         H: FamaPipe<Args, O>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            self.container()
-                .set_type(handler.receive_pipe_content(args).await)
-                .await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_store)
+            .await
     }
 
     // Stores Option<T> returned by the handler
     // If option is `none` the pipe flow is stopped
-    pub async fn some<H, Args, O: Clone + Send + Sync + 'static>(mut self, handler: H) -> Self
+    pub async fn some<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
     where
         H: FamaPipe<Args, Option<O>>,
         Args: Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            let option = handler.receive_pipe_content(args).await;
-
-            if option.is_none() {
-                self.container().set(PipeState::Stop).await;
-            }
-
-            self.container().set_type(option).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
-        }
-
-        self
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_some)
+            .await
     }
 
     // Stores Result<T, E> returned by the handler
     // If result is `err` the pipe flow is stopped
     pub async fn ok<H, Args, O: Clone + Send + Sync + 'static, E: Clone + Send + Sync + 'static>(
-        mut self,
+        self,
         handler: H,
     ) -> Self
     where
         H: FamaPipe<Args, Result<O, E>>,
         Args: busybody::Resolver + 'static,
     {
-        if *self.container().get::<PipeState>().await.unwrap() == PipeState::Run {
-            let args = Args::resolve(self.container()).await;
-            let result = handler.receive_pipe_content(args).await;
-
-            if result.is_err() {
-                self.container().set(PipeState::Stop).await;
-            }
+        self.describe_pipe(&handler)
+            .run_ok_pipe(|args| handler.receive_pipe_content(args))
+            .await
+    }
 
-            self.container().set_type(result).await;
-            self.went_through = true;
-        } else {
-            self.went_through = false;
+    /// Registers a handler that undoes the work of the previous pipe.
+    /// When the flow has been stopped or failed by the time the pipeline
+    /// is finished, the handlers registered so far run in reverse order.
+    /// A pipeline built directly must be finished with `Pipeline::finish`
+    /// for the handlers to run; `PipelineTrait`, `PipelineDef::run` and
+    /// `PipelineWorker` finish the pipelines they run.
+    /// A flow put back on track by `recover_fn` is not compensated.
+    /// Handlers registered in nested pipelines, join branches and
+    /// `for_each_fn` runs are taken over by the parent pipeline.
+    /// Their arguments are resolved from the pipe content. Nothing is
+    /// registered if the previous pipe did not run to completion. The
+    /// results are available via `Pipeline::compensations`
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let pipeline = fama::Pipeline::pass(1)
+    ///     .await
+    ///     .through_fn(|id: i32| async move { println!("creating user {}", id) })
+    ///     .await
+    ///     .compensate_fn(|id: i32| async move {
+    ///         println!("deleting user {}", id);
+    ///         Ok::<(), String>(())
+    ///     })
+    ///     .ok_fn(|| async { Err::<(), _>("card declined") })
+    ///     .await
+    ///     .finish()
+    ///     .await;
+    ///
+    /// assert!(pipeline.compensations()[0].succeeded());
+    ///# }
+    /// ```
+    pub fn compensate_fn<H, Args, E>(mut self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<(), E>>,
+        Args: busybody::Resolver + Send + 'static,
+        E: Send + Sync + 'static,
+    {
+        if let Some(record) = self.log.last()
+            && record.status == PipeStatus::Ran
+        {
+            self.compensations
+                .push((record.index, compensate_with(handler)));
         }
 
         self
     }
 
-    /// Returns the passed variable
-    pub async fn deliver(&self) -> T {
-        self.try_to_deliver().await.unwrap()
+    /// Runs the pipe only when the flow has been stopped.
+    /// Besides the usual arguments, the pipe can take the `FlowOutcome`,
+    /// the `StopReason` or the stored `Result` that failed the flow.
+    /// Returning `true` puts the flow back into the run state and the
+    /// pipes that follow run as usual. The pipe is not logged when the
+    /// flow is running
+    ///
+    /// ```rust
+    ///# use fama::PipeContent;
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let price = fama::Pipeline::pass(0)
+    ///     .await
+    ///     .ok_fn(|| async { Err::<i32, _>("service unavailable") })
+    ///     .await
+    ///     .recover_fn(|pipe: PipeContent| async move {
+    ///         pipe.store(42).await; // the cached price
+    ///         true
+    ///     })
+    ///     .await
+    ///     .store_fn(|price: i32| async move { price + 1 })
+    ///     .await
+    ///     .deliver()
+    ///     .await;
+    ///
+    /// assert_eq!(price, 43);
+    ///# }
+    /// ```
+    pub async fn recover_fn<H, Args>(self, mut handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        self.name_pipe::<H>()
+            .run_recovery(|args| handler.pipe_fn_handle(args))
+            .await
     }
 
-    /// Returns the passed variable wrapped in an `Option<T>`
-    pub async fn try_to_deliver(&self) -> Option<T> {
-        self.container().get_type().await
+    /// Accepts an instance of a struct that implements `fama::FamaPipe`.
+    /// See `Pipeline::recover_fn`
+    pub async fn recover<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        self.describe_pipe(&handler)
+            .run_recovery(|args| handler.receive_pipe_content(args))
+            .await
     }
 
-    /// Returns a different type that may have been set
-    /// by one of the pipes
-    pub async fn deliver_as<R: Clone + 'static>(&self) -> R {
-        self.try_deliver_as().await.unwrap()
+    /// Runs the pipe whether or not the flow has been stopped.
+    /// Finalizers run where they are registered, after the pipes before
+    /// them. They can take the `FlowOutcome` as an argument, but cannot
+    /// change the outcome of a flow that already ended. The pipeline
+    /// deadline does not apply to them
+    ///
+    /// ```rust
+    ///# use fama::FlowOutcome;
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let pipeline = fama::Pipeline::pass(0)
+    ///     .await
+    ///     .next_fn(|| async { false })
+    ///     .await
+    ///     .finally_fn(|outcome: FlowOutcome| async move {
+    ///         println!("releasing the lock, the flow was stopped: {}", outcome.is_stopped());
+    ///     })
+    ///     .await;
+    ///
+    /// assert_eq!(pipeline.execution_log().ran().count(), 2);
+    ///# }
+    /// ```
+    pub async fn finally_fn<H, Args, O>(mut self, mut handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + 'static,
+    {
+        self.next_pipe.finalizer = true;
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_through)
+            .await
     }
 
-    /// Returns a different type that may have been set
-    /// by one of the pipes. The returned type will be wrapped
-    /// in an `Option<T>`
-    pub async fn try_deliver_as<R: Clone + 'static>(&self) -> Option<R> {
-        self.container().get_type().await
+    /// Accepts an instance of a struct that implements `fama::FamaPipe`.
+    /// See `Pipeline::finally_fn`
+    pub async fn finally<H, Args, O>(mut self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O>,
+        Args: busybody::Resolver + 'static,
+    {
+        self.next_pipe.finalizer = true;
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_through)
+            .await
     }
 
-    /// Returns true if the content went through all the registered pipes
-    pub fn confirm(&self) -> bool {
-        self.went_through
+    /// Turns the content into a `U` and carries on with a `Pipeline<U>`.
+    /// The pipes that follow, `deliver` and `PipelineTrait` work with the
+    /// new content. The previous content stays available via `deliver_as`.
+    /// When the flow has been stopped, the pipe is skipped and there is
+    /// no `U` to deliver: `try_to_deliver` returns `None`, `deliver`
+    /// panics and `deliver_result` returns the outcome that skipped it
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// #[derive(Clone)]
+    /// struct RawSignup(String);
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct ValidatedSignup {
+    ///     email: String,
+    /// }
+    ///
+    /// let signup: ValidatedSignup = fama::Pipeline::pass(RawSignup(" Ada@Example.com ".into()))
+    ///     .await
+    ///     .map_fn(|raw: RawSignup| async move {
+    ///         ValidatedSignup {
+    ///             email: raw.0.trim().to_lowercase(),
+    ///         }
+    ///     })
+    ///     .await
+    ///     .deliver()
+    ///     .await;
+    ///
+    /// assert_eq!(signup.email, "ada@example.com");
+    ///# }
+    /// ```
+    pub async fn map_fn<H, Args, U>(self, mut handler: H) -> Pipeline<U>
+    where
+        H: PipeFnHandler<Args, U>,
+        Args: busybody::Resolver + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        let since = PipeContent::write_sequence();
+        self.name_pipe::<H>()
+            .run_pipe(|args| handler.pipe_fn_handle(args), settle_store)
+            .await
+            .retype_mapped(since)
     }
 
-    fn container(&self) -> &busybody::ServiceContainer {
-        self.pipe_content.container()
+    /// Accepts an instance of a struct that implements `fama::FamaPipe`.
+    /// See `Pipeline::map_fn`
+    pub async fn map<H, Args, U>(self, handler: H) -> Pipeline<U>
+    where
+        H: FamaPipe<Args, U>,
+        Args: busybody::Resolver + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        let since = PipeContent::write_sequence();
+        self.describe_pipe(&handler)
+            .run_pipe(|args| handler.receive_pipe_content(args), settle_store)
+            .await
+            .retype_mapped(since)
     }
-}
 
-#[async_trait]
-pub trait FamaPipe<Args, O> {
-    /// Where a pipe logic resides
-    async fn receive_pipe_content(&self, args: Args) -> O;
+    /// Runs the branches of `join` concurrently and waits for all of them.
+    /// The flow stops when one of the branches stops it. Values stored by
+    /// the branches are combined as configured on the `Join`.
+    /// Each branch gets a copy of the values stored with
+    /// `PipeContent::store`. Values set directly on the container, such
+    /// as with `container().set_type`, are not visible to the branches
+    pub async fn through_all(self, join: Join<T>) -> Self {
+        let settings = self.settings.clone();
+        self.name_pipe::<Join<T>>()
+            .run_pipe(
+                |parent: PipeContent| join.run(parent, settings.clone()),
+                settle_flow,
+            )
+            .await
+    }
 
-    /// Wraps the type in a Box
-    fn to_pipe(self) -> Box<Self>
+    /// Runs `def` as a single pipe of this pipeline.
+    /// The nested pipeline gets a copy of the values stored so far with
+    /// `PipeContent::store`. Values set directly on the container, such as
+    /// with `container().set_type`, are not copied. Its writes stay in the
+    /// copy, except the values written with `PipeContent::export`. The flow
+    /// stops when the nested pipeline stops
+    ///
+    /// ```rust
+    ///# use fama::{PipeContent, PipelineDef};
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let audit = PipelineDef::new().through_fn(|num: i32, pipe: PipeContent| async move {
+    ///     pipe.store(0).await;
+    ///     pipe.export(format!("saw {}", num)).await;
+    /// });
+    ///
+    /// let pipeline = fama::Pipeline::pass(42)
+    ///     .await
+    ///     .through_pipeline(audit)
+    ///     .await;
+    ///
+    /// assert_eq!(pipeline.deliver().await, 42);
+    /// assert_eq!(pipeline.deliver_as::<String>().await, "saw 42");
+    ///# }
+    /// ```
+    pub async fn through_pipeline(self, def: PipelineDef<T>) -> Self {
+        self.name_pipe::<PipelineDef<T>>().nest(def).await
+    }
+
+    /// Runs the pipeline registered for `U` as a nested pipeline.
+    /// The stored `U` is its content. See `Pipeline::through_pipeline`
+    pub async fn through_builder<U: PipelineBuilderTrait + 'static>(self) -> Self {
+        let def = U::pipeline_builder().await.definition().await;
+        self.name_pipe::<U>().nest(def).await
+    }
+
+    async fn nest<U: Clone + Send + Sync + 'static>(self, def: PipelineDef<U>) -> Self {
+        let settings = self.settings.clone();
+        self.run_pipe(
+            |parent: PipeContent| def.run_nested(parent, settings.clone()),
+            settle_flow,
+        )
+        .await
+    }
+
+    /// Runs the `then` sub chain when the predicate returns true.
+    /// The predicate is a pipe and receives its arguments the same way.
+    /// The flow carries on after the branch, unless a pipe in the branch
+    /// stopped it
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let total = fama::Pipeline::pass(20)
+    ///     .await
+    ///     .when_fn(
+    ///         |num: i32| async move { num > 10 },
+    ///         |p| async { p.store_fn(|num: i32| async move { num - 10 }).await },
+    ///     )
+    ///     .await
+    ///     .otherwise(|p| async { p.store_fn(|_: i32| async { 0 }).await })
+    ///     .await
+    ///     .deliver()
+    ///     .await;
+    ///
+    /// assert_eq!(total, 10);
+    ///# }
+    /// ```
+    pub async fn when_fn<H, Args, F, Fut>(self, predicate: H, then: F) -> Self
     where
-        Self: Sized,
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
     {
-        Box::new(self)
+        self.branch_on(predicate, true, then).await
     }
-}
 
-pub trait PipeFnHandler<Args, O>: Send + Sync + 'static {
-    type Future: Future<Output = O> + Send;
+    /// Runs the `then` sub chain when the predicate returns false.
+    /// See `Pipeline::when_fn`
+    pub async fn unless_fn<H, Args, F, Fut>(self, predicate: H, then: F) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        self.branch_on(predicate, false, then).await
+    }
 
-    fn pipe_fn_handle(&mut self, args: Args) -> Self::Future;
-}
+    /// Runs the sub chain when the preceding `when_fn` or `unless_fn`
+    /// did not run its branch
+    pub async fn otherwise<F, Fut>(mut self, then: F) -> Self
+    where
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        if self.branch_taken.take() == Some(false) {
+            return then(self).await;
+        }
 
-impl<Func, Fut, O> PipeFnHandler<(), O> for Func
-where
-    Func: Send + Sync + FnMut() -> Fut + Send + Sync + 'static,
-    Fut: Future<Output = O> + Send,
-{
-    type Future = Fut;
-    fn pipe_fn_handle(&mut self, _: ()) -> Self::Future {
-        (self)()
+        self
     }
-}
 
-impl<Func, Arg1, Fut, O> PipeFnHandler<(Arg1,), O> for Func
-where
-    Func: Send + Sync + FnMut(Arg1) -> Fut + Send + Sync + 'static,
-    Fut: Future<Output = O> + Send,
-{
-    type Future = Fut;
-    fn pipe_fn_handle(&mut self, (c,): (Arg1,)) -> Self::Future {
-        (self)(c)
+    async fn branch_on<H, Args, F, Fut>(mut self, mut predicate: H, expected: bool, then: F) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        let mut matched = None;
+        self = self
+            .name_pipe::<H>()
+            .run_pipe(
+                |args| predicate.pipe_fn_handle(args),
+                |result: bool, _| {
+                    matched = Some(result);
+                    ready(Flow::Continue)
+                },
+            )
+            .await;
+
+        let taken = matched == Some(expected);
+        if taken {
+            self = then(self).await;
+        }
+        self.branch_taken = matched.map(|_| taken);
+
+        self
     }
-}
 
-macro_rules! pipe_func{
-    ($($T: ident),*) => {
-        impl<Func, $($T),+, Fut, O> PipeFnHandler <($($T),+), O> for Func
-         where Func: FnMut($($T),+) -> Fut + Send + Sync + 'static,
-         Fut: Future<Output = O> + Send,
-        {
-            type Future = Fut;
+    /// Runs the `body` sub chain for as long as the condition returns true.
+    /// The condition is a pipe and is called before each iteration.
+    /// When it is still true after `max_iterations`, the flow fails
+    /// with an `IterationLimit` error
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let pages = fama::Pipeline::pass(0)
+    ///     .await
+    ///     .repeat_while_fn(
+    ///         |page: i32| async move { page < 3 },
+    ///         10,
+    ///         |p| async { p.store_fn(|page: i32| async move { page + 1 }).await },
+    ///     )
+    ///     .await
+    ///     .deliver()
+    ///     .await;
+    ///
+    /// assert_eq!(pages, 3);
+    ///# }
+    /// ```
+    pub async fn repeat_while_fn<H, Args, F, Fut>(
+        mut self,
+        mut condition: H,
+        max_iterations: usize,
+        mut body: F,
+    ) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+        F: FnMut(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        // Each check of the condition is logged under the same name
+        self = self.name_pipe::<H>();
+        let name = self.next_pipe.name.clone();
+        let mut iterations = 0;
+        loop {
+            let mut repeat = false;
+            if self.next_pipe.name.is_none() {
+                self.next_pipe.name = name.clone();
+            }
+            self = self
+                .run_pipe(
+                    |args| condition.pipe_fn_handle(args),
+                    |holds: bool, _| {
+                        if holds && iterations == max_iterations {
+                            return ready(Flow::Fail(Reason::new(IterationLimit {
+                                max_iterations,
+                            })));
+                        }
+                        repeat = holds;
+                        ready(Flow::Continue)
+                    },
+                )
+                .await;
 
-            #[allow(non_snake_case)]
-            fn pipe_fn_handle(&mut self, ($($T),+): ($($T),+)) -> Self::Future {
-                (self)($($T),+)
+            if !repeat {
+                return self;
+            }
+
+            self = body(self).await;
+            iterations += 1;
+        }
+    }
+
+    /// Runs `body` once per item returned by the pipe. Each run receives a
+    /// copy of the pipe content with the item as its content. The delivered
+    /// items are stored as a `Vec` in the same order. The flow stops when
+    /// one of the runs stops, and the items that follow are not processed.
+    /// Items are processed one after the other unless `concurrency` is set,
+    /// in which case the runs already started alongside still finish
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let squares = fama::Pipeline::pass(vec![1, 2, 3])
+    ///     .await
+    ///     .concurrency(2)
+    ///     .for_each_fn(
+    ///         |items: Vec<i32>| async move { items },
+    ///         fama::PipelineDef::new().store_fn(|num: i32| async move { num * num }),
+    ///     )
+    ///     .await
+    ///     .deliver()
+    ///     .await;
+    ///
+    /// assert_eq!(squares, vec![1, 4, 9]);
+    ///# }
+    /// ```
+    pub async fn for_each_fn<H, Args, I>(mut self, mut items: H, body: PipelineDef<I>) -> Self
+    where
+        H: PipeFnHandler<Args, Vec<I>>,
+        Args: busybody::Resolver + 'static,
+        I: Clone + Send + Sync + 'static,
+    {
+        let limit = self.next_pipe.concurrency.take().unwrap_or(1);
+        let settings = self.settings.clone();
+        self.name_pipe::<H>()
+            .run_pipe(
+                |args| items.pipe_fn_handle(args),
+                |items, parent| settle_each(items, parent, body, settings, limit),
+            )
+            .await
+    }
+
+    /// Routes the content to one of the following `case` arms.
+    /// The selector is a pipe and receives its arguments the same way.
+    /// The first `case` with a key equal to the selected one runs.
+    /// When none matches, the `default` arm runs. A `case` key of another
+    /// type than `K` fails the flow with a `CaseKeyMismatch` error
+    ///
+    /// ```rust
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// #[derive(Clone)]
+    /// enum Command {
+    ///     Create(String),
+    ///     Delete(u32),
+    /// }
+    ///
+    /// let pipeline = fama::Pipeline::pass(Command::Delete(7))
+    ///     .await
+    ///     .switch_fn(|cmd: Command| async move {
+    ///         match cmd {
+    ///             Command::Create(_) => "create",
+    ///             Command::Delete(_) => "delete",
+    ///         }
+    ///     })
+    ///     .await
+    ///     .case("create", |p| async { p.store_fn(|| async { "created" }).await })
+    ///     .await
+    ///     .case("delete", |p| async { p.store_fn(|| async { "deleted" }).await })
+    ///     .await
+    ///     .default(|p| async { p.store_fn(|| async { "ignored" }).await })
+    ///     .await;
+    ///
+    /// assert_eq!(pipeline.deliver_as::<&str>().await, "deleted");
+    ///# }
+    /// ```
+    pub async fn switch_fn<H, Args, K>(mut self, mut selector: H) -> Self
+    where
+        H: PipeFnHandler<Args, K>,
+        Args: busybody::Resolver + 'static,
+        K: PartialEq + Send + Sync + 'static,
+    {
+        let pipe = self.log.len();
+        let mut key = None;
+        self = self
+            .name_pipe::<H>()
+            .run_pipe(
+                |args| selector.pipe_fn_handle(args),
+                |selected: K, _| {
+                    key = Some(selected);
+                    ready(Flow::Continue)
+                },
+            )
+            .await;
+
+        self.selected = key.map(|key| Selected {
+            pipe,
+            key: Arc::new(key),
+            key_type: type_name::<K>(),
+            matched: false,
+        });
+
+        self
+    }
+
+    /// Runs the sub chain when `key` equals the key returned by the
+    /// preceding `switch_fn` selector and no earlier arm ran.
+    /// `key` must be of the type the selector returns
+    pub async fn case<K, F, Fut>(mut self, key: K, then: F) -> Self
+    where
+        K: PartialEq + 'static,
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        let Some(selected) = self.selected.take() else {
+            return self;
+        };
+
+        if !selected.matched && !selected.key.is::<K>() {
+            let mismatch = CaseKeyMismatch {
+                selected: selected.key_type,
+                case: type_name::<K>(),
+            };
+            self.end_flow(selected.pipe, Flow::Fail(Reason::new(mismatch)))
+                .await;
+            return self;
+        }
+
+        if selected.matched || selected.key.downcast_ref::<K>() != Some(&key) {
+            self.selected = Some(selected);
+            return self;
+        }
+
+        self = then(self).await;
+        self.selected = Some(Selected {
+            matched: true,
+            ..selected
+        });
+
+        self
+    }
+
+    /// Runs the sub chain when no `case` of the preceding `switch_fn`
+    /// matched. This ends the switch
+    pub async fn default<F, Fut>(mut self, then: F) -> Self
+    where
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = Self>,
+    {
+        match self.selected.take() {
+            Some(selected) if !selected.matched => then(self).await,
+            _ => self,
+        }
+    }
+
+    /// Returns the passed variable.
+    /// Panics when there is none, see `Pipeline::deliver_result`
+    pub async fn deliver(&self) -> T {
+        self.try_to_deliver().await.expect(
+            "the pipeline has no content to deliver, the flow stopped before `map` produced it. \
+             Use `deliver_result` or `try_to_deliver` instead",
+        )
+    }
+
+    /// Returns the passed variable wrapped in an `Option<T>`.
+    /// `None` when a `map` pipe was skipped and no content has been
+    /// stored since
+    pub async fn try_to_deliver(&self) -> Option<T> {
+        if !self.has_content() {
+            return None;
+        }
+        self.container().get_type().await
+    }
+
+    /// Returns a different type that may have been set
+    /// by one of the pipes
+    pub async fn deliver_as<R: Clone + 'static>(&self) -> R {
+        self.try_deliver_as().await.unwrap()
+    }
+
+    /// Returns a different type that may have been set
+    /// by one of the pipes. The returned type will be wrapped
+    /// in an `Option<T>`. This reads the last stored value of `R`
+    /// as is: unlike `try_to_deliver`, it also returns a value stored
+    /// before a `map` pipe that was skipped
+    pub async fn try_deliver_as<R: Clone + 'static>(&self) -> Option<R> {
+        self.container().get_type().await
+    }
+
+    /// Returns the content when the flow completed.
+    /// When a pipe failed the flow with an `E`, that error is returned.
+    /// Any other outcome is converted into an `E`. A flow that was
+    /// recovered without a content after a skipped `map` returns the
+    /// outcome that skipped it
+    pub async fn deliver_result<E>(&self) -> Result<T, E>
+    where
+        E: From<FlowOutcome> + Clone + 'static,
+    {
+        match &self.outcome {
+            FlowOutcome::Completed => match self.try_to_deliver().await {
+                Some(content) => Ok(content),
+                None => Err(E::from(
+                    self.unmapped
+                        .as_ref()
+                        .map(|unmapped| unmapped.outcome.clone())
+                        .unwrap_or_default(),
+                )),
+            },
+            FlowOutcome::Failed { error, .. } if error.is::<E>() => {
+                Err(error.downcast_ref::<E>().cloned().unwrap())
+            }
+            outcome => Err(E::from(outcome.clone())),
+        }
+    }
+
+    /// Continues with a pipeline whose pipes fail with an `E`.
+    /// See `TryPipeline`
+    pub fn with_error<E>(self) -> TryPipeline<T, E>
+    where
+        E: From<FlowOutcome> + Clone + Send + Sync + 'static,
+    {
+        TryPipeline::new(self)
+    }
+
+    /// Returns true if the flow completed: the content went through all
+    /// the registered pipes, or a recovery pipe put the flow back on track
+    pub fn confirm(&self) -> bool {
+        self.outcome.is_completed()
+    }
+
+    /// Returns one entry per pipe, in the order the pipes were registered
+    pub fn execution_log(&self) -> &ExecutionLog {
+        &self.log
+    }
+
+    /// Returns how the flow ended: completed, stopped or failed.
+    /// A stopped or failed outcome includes the index of the pipe
+    /// that ended the flow
+    pub fn outcome(&self) -> &FlowOutcome {
+        &self.outcome
+    }
+
+    /// Returns the result of each compensation handler that ran,
+    /// in the order they ran. Handlers only run once the pipeline is
+    /// finished, so this stays empty until `Pipeline::finish` is called
+    pub fn compensations(&self) -> &[Compensation] {
+        &self.compensated
+    }
+
+    /// Returns each pipe with its name, start offset, duration, status
+    /// and the types it stored. See `PipelineReport`
+    pub fn report(&self) -> PipelineReport {
+        PipelineReport::new(type_name::<T>(), self.outcome.clone(), self.log.to_vec())
+    }
+
+    /// Returns the panic that failed the flow, if any
+    pub fn panicked(&self) -> Option<&PipePanic> {
+        match &self.outcome {
+            FlowOutcome::Failed { error, .. } => error.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Carries on with the `U` stored by the `map` pipe that started at
+    /// `since`, or with no content when the pipe did not produce one.
+    /// A pipe that produced its `U` and then stopped the flow still
+    /// produced the content
+    fn retype_mapped<U: Clone + Send + Sync + 'static>(self, since: u64) -> Pipeline<U> {
+        let produced = self
+            .pipe_content
+            .stored_since(since)
+            .iter()
+            .any(|entry| entry.type_id == TypeId::of::<U>());
+        let unmapped = (!produced).then(|| Unmapped {
+            since: PipeContent::write_sequence(),
+            outcome: self.outcome.clone(),
+        });
+
+        Pipeline {
+            phantom: PhantomData,
+            pipe_content: self.pipe_content,
+            log: self.log,
+            outcome: self.outcome,
+            settings: self.settings,
+            next_pipe: self.next_pipe,
+            branch_taken: self.branch_taken,
+            selected: self.selected,
+            compensations: self.compensations,
+            compensated: self.compensated,
+            span: self.span,
+            started: self.started,
+            unmapped,
+            finished: self.finished,
+            name: self.name,
+        }
+    }
+
+    /// Marks the end of a run. Runs the compensation handlers when the
+    /// flow did not complete and records the metrics of the pipeline.
+    /// Required for a pipeline built directly that registers compensations.
+    /// Finishing a pipeline again has no effect.
+    /// `PipelineTrait`, `PipelineDef::run` and `PipelineWorker` finish
+    /// the pipelines they run
+    pub async fn finish(mut self) -> Self {
+        if self.finished {
+            return self;
+        }
+        self.finished = true;
+        self.settle().await;
+
+        #[cfg(feature = "metrics")]
+        metrics::record_pipeline(
+            &self.name,
+            MetricOutcome::from(&self.outcome),
+            self.started.elapsed(),
+        );
+
+        self
+    }
+
+    /// False when a `map` pipe was skipped and no content has been
+    /// stored since
+    fn has_content(&self) -> bool {
+        self.unmapped.as_ref().is_none_or(|unmapped| {
+            self.pipe_content
+                .stored_since(unmapped.since)
+                .iter()
+                .any(|entry| entry.type_id == TypeId::of::<T>())
+        })
+    }
+
+    /// Passes the compensation handlers of this nested run to the pipeline
+    /// that owns `parent`. They run against this run's content, and only
+    /// if the parent flow ends up stopped or failed
+    pub(crate) fn hand_up_compensations(&mut self, parent: &PipeContent) {
+        let content = self.pipe_content.clone();
+        parent.hand_up(self.compensations.drain(..).map(|(_, compensate)| {
+            let content = content.clone();
+            Arc::new(move |_: PipeContent| compensate(content.clone())) as Compensate
+        }));
+    }
+
+    pub(crate) fn pipe_content(&self) -> &PipeContent {
+        &self.pipe_content
+    }
+
+    /// Names the next pipe after `H`, unless it already has a name
+    pub(crate) fn name_pipe<H>(mut self) -> Self {
+        if self.next_pipe.name.is_none() {
+            self.next_pipe.name = Some(type_name::<H>().to_string());
+        }
+        self
+    }
+
+    /// Fills in the name and the metadata of the next pipe from `pipe`,
+    /// where they were not set on the pipeline
+    pub(crate) fn describe_pipe<H: FamaPipe<Args, O>, Args, O>(mut self, pipe: &H) -> Self {
+        let options = &mut self.next_pipe;
+        options.name.get_or_insert_with(|| pipe.name());
+        if options.description.is_none() {
+            options.description = pipe.description();
+        }
+        if options.tags.is_empty() {
+            options.tags = pipe.tags();
+        }
+        self
+    }
+
+    fn container(&self) -> &busybody::ServiceContainer {
+        self.pipe_content.container()
+    }
+
+    async fn run_pipe<Args, O, C, Fut, S, SFut>(self, call: C, settle: S) -> Self
+    where
+        Args: Resolver,
+        C: FnMut(Args) -> Fut,
+        Fut: Future<Output = O>,
+        S: FnOnce(O, PipeContent) -> SFut,
+        SFut: Future<Output = Flow>,
+    {
+        self.invoke_pipe(call, no_error, settle).await
+    }
+
+    /// Runs a pipe returning a `Result`. Only these pipes can be retried
+    async fn run_ok_pipe<Args, O, E, C, Fut>(self, call: C) -> Self
+    where
+        Args: Resolver,
+        O: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+        C: FnMut(Args) -> Fut,
+        Fut: Future<Output = Result<O, E>>,
+    {
+        self.run_result_pipe(call, settle_ok).await
+    }
+
+    /// Runs a pipe returning a `Result` and lets `settle` decide
+    /// what to do with it
+    pub(crate) async fn run_result_pipe<Args, O, E, C, Fut, S, SFut>(
+        self,
+        call: C,
+        settle: S,
+    ) -> Self
+    where
+        Args: Resolver,
+        E: Send + Sync + 'static,
+        C: FnMut(Args) -> Fut,
+        Fut: Future<Output = Result<O, E>>,
+        S: FnOnce(Result<O, E>, PipeContent) -> SFut,
+        SFut: Future<Output = Flow>,
+    {
+        self.invoke_pipe(call, result_error, settle).await
+    }
+
+    /// Runs a recovery pipe with the flow running again. The flow is put
+    /// back into its stopped state unless the pipe returned `true`
+    async fn run_recovery<Args, C, Fut>(mut self, call: C) -> Self
+    where
+        Args: Resolver,
+        C: FnMut(Args) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        if self.pipe_content.is_running().await {
+            self.next_pipe = PipeOptions::default();
+            self.branch_taken = None;
+            self.selected = None;
+            return self;
+        }
+
+        let outcome = std::mem::take(&mut self.outcome);
+        self.pipe_content.resume().await;
+
+        let mut resumed = false;
+        self = self
+            .run_pipe(call, |resume: bool, _| {
+                resumed = resume;
+                ready(Flow::Continue)
+            })
+            .await;
+
+        if !self.outcome.is_completed() {
+            return self;
+        }
+
+        if resumed {
+            self.container().set_type(StopReason::default()).await;
+            self.container().set_type(FlowOutcome::Completed).await;
+        } else {
+            let reason = match &outcome {
+                FlowOutcome::Stopped { reason, .. } => reason.clone(),
+                _ => StopReason::Unspecified,
+            };
+            self.pipe_content.halt(reason).await;
+            self.outcome = outcome;
+        }
+
+        self
+    }
+
+    /// Resolves the arguments, calls the pipe and applies what `settle`
+    /// decided. The pipe is skipped when the flow has been stopped.
+    /// When a retry policy is set and `error` finds a retryable error in
+    /// the output, the arguments are resolved again and the pipe recalled.
+    async fn invoke_pipe<Args, O, C, Fut, S, SFut>(
+        mut self,
+        mut call: C,
+        error: fn(&O) -> Option<&(dyn Any + Send + Sync)>,
+        settle: S,
+    ) -> Self
+    where
+        Args: Resolver,
+        C: FnMut(Args) -> Fut,
+        Fut: Future<Output = O>,
+        S: FnOnce(O, PipeContent) -> SFut,
+        SFut: Future<Output = Flow>,
+    {
+        let index = self.log.len();
+        let options = std::mem::take(&mut self.next_pipe);
+        self.branch_taken = None;
+        self.selected = None;
+
+        let started = Instant::now();
+        let span = trace::pipe_span(&self.span, index, options.name.as_deref(), type_name::<T>());
+        let record = PipeRecord {
+            index,
+            name: options.name,
+            description: options.description,
+            tags: options.tags,
+            status: PipeStatus::Ran,
+            offset: started.saturating_duration_since(self.started),
+            duration: Duration::ZERO,
+            attempts: 0,
+            stored: Vec::new(),
+        };
+
+        let mut running = self.pipe_content.is_running().await;
+        if !running && !options.finalizer {
+            self.record(&span, record, PipeStatus::Skipped, 0);
+            return self;
+        }
+
+        // A finalizer ignores the pipeline deadline and cannot
+        // change how a flow that already ended, ended
+        let since = PipeContent::write_sequence();
+        let pipeline_deadline = self.settings.deadline.filter(|_| !options.finalizer);
+        let deadline = match (options.timeout, pipeline_deadline) {
+            (Some(timeout), Some(deadline)) => Some(deadline.min(started + timeout)),
+            (Some(timeout), None) => Some(started + timeout),
+            (None, deadline) => deadline,
+        };
+
+        // A finalizer still runs once the flow was cancelled at it
+        if running && self.settings.cancellation.is_cancelled() {
+            self.end_flow(index, Flow::Cancel).await;
+            if !options.finalizer {
+                self.record(&span, record, PipeStatus::Skipped, 0);
+                return self;
+            }
+            running = false;
+        }
+
+        if running && deadline.is_some_and(|deadline| deadline <= started) {
+            self.end_flow(index, Flow::TimeOut).await;
+            self.record(&span, record, PipeStatus::Skipped, 0);
+            return self;
+        }
+
+        // An interceptor stopping the flow takes the place of the pipe
+        let interceptors = interceptor::interceptors(&self.settings.interceptors);
+        let info = (!interceptors.is_empty()).then(|| PipeInfo {
+            index,
+            name: record.name.clone(),
+            description: record.description.clone(),
+            tags: record.tags.clone(),
+            content: type_name::<T>(),
+            started,
+        });
+        if let Some(info) = &info {
+            for interceptor in &interceptors {
+                interceptor.before(info, &self.pipe_content).await;
+            }
+        }
+        let intercepted = running && info.is_some() && !self.pipe_content.is_running().await;
+        if intercepted {
+            self.end_flow(index, Flow::Continue).await;
+            running = false;
+        }
+        let skip_pipe = intercepted && !options.finalizer;
+        // A finalizer stopping a flow that already ended keeps its reason
+        let reason = match running {
+            false => Some(self.pipe_content.stop_reason().await),
+            true => None,
+        };
+
+        let pipe_content = self.pipe_content.clone();
+        let retry = options.retry;
+        let mut attempts = 0;
+        let invoke = async {
+            let mut wait = None;
+            loop {
+                if let Some(wait) = wait.take() {
+                    tokio::time::sleep(wait).await;
+                }
+
+                attempts += 1;
+                let args = Args::resolve(pipe_content.container()).await;
+                let output = match retry_wait(retry.as_ref(), attempts, error, call(args).await) {
+                    Ok(output) => output,
+                    Err(delay) => {
+                        wait = Some(delay);
+                        continue;
+                    }
+                };
+
+                break settle(output, pipe_content.clone()).await;
+            }
+        };
+
+        let catch_panic = self.settings.catch_panics || options.catch_panic;
+        let name = record.name.clone();
+        let guarded = async {
+            if catch_panic {
+                AssertUnwindSafe(invoke)
+                    .catch_unwind()
+                    .await
+                    .unwrap_or_else(|payload| {
+                        Flow::Fail(Reason::new(PipePanic::new(index, name, payload)))
+                    })
+            } else {
+                invoke.await
+            }
+        };
+
+        let guarded = trace::instrument(&span, guarded);
+        let flow = match deadline {
+            _ if skip_pipe => {
+                drop(guarded);
+                Flow::Continue
+            }
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), guarded)
+                .await
+                .unwrap_or(Flow::TimeOut),
+            None => guarded.await,
+        };
+
+        if let Some(reason) = reason {
+            self.pipe_content.halt(reason).await;
+        }
+
+        // The work of the nested runs is undone along with this pipe
+        for compensate in self.pipe_content.take_handed_up() {
+            self.compensations.push((index, compensate));
+        }
+
+        if running {
+            self.end_flow(index, flow).await;
+        }
+
+        if let Some(info) = &info {
+            for interceptor in interceptors.iter().rev() {
+                interceptor
+                    .after(info, &self.pipe_content, &self.outcome)
+                    .await;
+            }
+        }
+
+        // A pipe the flow ended at before it was called did not run
+        let status = if skip_pipe {
+            PipeStatus::Skipped
+        } else if self.outcome.at_pipe() == Some(index) {
+            PipeStatus::Stopped
+        } else {
+            PipeStatus::Ran
+        };
+        let duration = started.elapsed();
+        let stored = self.pipe_content.stored_since(since);
+        let record = PipeRecord {
+            duration,
+            stored: stored.iter().map(|entry| entry.value.type_name()).collect(),
+            ..record
+        };
+        self.record(&span, record, status, attempts);
+
+        self
+    }
+
+    /// Applies the decision made after the pipe at `index` returned
+    async fn end_flow(&mut self, index: usize, flow: Flow) {
+        match flow {
+            Flow::Fail(error) => {
+                self.pipe_content.halt(StopReason::Unspecified).await;
+                self.outcome = FlowOutcome::Failed {
+                    at_pipe: index,
+                    error,
+                };
+            }
+            Flow::TimeOut => {
+                self.pipe_content.halt(StopReason::Unspecified).await;
+                self.outcome = FlowOutcome::TimedOut { at_pipe: index };
+            }
+            Flow::Cancel => {
+                self.pipe_content.halt(StopReason::Unspecified).await;
+                self.outcome = FlowOutcome::Cancelled { at_pipe: index };
+            }
+            Flow::Stop(reason) => {
+                self.pipe_content.halt(reason.clone()).await;
+                self.outcome = FlowOutcome::Stopped {
+                    at_pipe: index,
+                    reason,
+                };
+            }
+            Flow::Continue if !self.pipe_content.is_running().await => {
+                self.outcome = FlowOutcome::Stopped {
+                    at_pipe: index,
+                    reason: self.pipe_content.stop_reason().await,
+                };
+            }
+            Flow::Continue => (),
+        }
+
+        if !self.outcome.is_completed() {
+            self.container().set_type(self.outcome.clone()).await;
+        }
+    }
+
+    /// Runs the registered compensation handlers, last one first,
+    /// when the flow did not complete. Nothing can recover the flow
+    /// once it is settled
+    async fn settle(&mut self) {
+        if self.outcome.is_completed() {
+            return;
+        }
+        while let Some((pipe, compensate)) = self.compensations.pop() {
+            let result = compensate(self.pipe_content.clone()).await;
+            self.compensated.push(Compensation { pipe, result });
+        }
+    }
+
+    fn record(&mut self, span: &Span, record: PipeRecord, status: PipeStatus, attempts: u32) {
+        trace::record_status(span, status);
+
+        #[cfg(feature = "metrics")]
+        if status != PipeStatus::Skipped {
+            let outcome = match status {
+                PipeStatus::Stopped => MetricOutcome::from(&self.outcome),
+                _ => MetricOutcome::Completed,
+            };
+            let pipe = match &record.name {
+                Some(name) => std::borrow::Cow::from(name.as_str()),
+                None => format!("#{}", record.index).into(),
+            };
+            metrics::record_pipe(&self.name, &pipe, outcome, record.duration);
+        }
+
+        self.log.push(PipeRecord {
+            status,
+            attempts,
+            ..record
+        });
+    }
+}
+
+/// Hands back the output, or how long to wait before the next attempt.
+/// Not an `async fn` so that borrowing `output` does not make the
+/// pipe future require `O: Send`
+fn retry_wait<O>(
+    policy: Option<&RetryPolicy>,
+    attempts: u32,
+    error: fn(&O) -> Option<&(dyn Any + Send + Sync)>,
+    output: O,
+) -> Result<O, Duration> {
+    match (policy, error(&output)) {
+        (Some(policy), Some(error)) if policy.should_retry(attempts, error) => {
+            Err(policy.delay(attempts))
+        }
+        _ => Ok(output),
+    }
+}
+
+fn no_error<O>(_: &O) -> Option<&(dyn Any + Send + Sync)> {
+    None
+}
+
+fn result_error<O, E: Send + Sync + 'static>(
+    result: &Result<O, E>,
+) -> Option<&(dyn Any + Send + Sync)> {
+    result
+        .as_ref()
+        .err()
+        .map(|error| error as &(dyn Any + Send + Sync))
+}
+
+fn settle_flow(flow: Flow, _: PipeContent) -> Ready<Flow> {
+    ready(flow)
+}
+
+// Not an `async fn` so that `O` is dropped right away and does
+// not have to be `Send`
+fn settle_through<O>(_: O, _: PipeContent) -> Ready<Flow> {
+    ready(Flow::Continue)
+}
+
+async fn settle_next(proceed: bool, _: PipeContent) -> Flow {
+    if proceed {
+        Flow::Continue
+    } else {
+        Flow::Stop(StopReason::ReturnedFalse)
+    }
+}
+
+async fn settle_store<O: Clone + Send + Sync + 'static>(value: O, pipe: PipeContent) -> Flow {
+    pipe.store(value).await;
+    Flow::Continue
+}
+
+async fn settle_some<O: Clone + Send + Sync + 'static>(
+    option: Option<O>,
+    pipe: PipeContent,
+) -> Flow {
+    let is_none = option.is_none();
+    pipe.store(option).await;
+
+    if is_none {
+        Flow::Stop(StopReason::ReturnedNone)
+    } else {
+        Flow::Continue
+    }
+}
+
+async fn settle_ok<O, E>(result: Result<O, E>, pipe: PipeContent) -> Flow
+where
+    O: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    let error = result.as_ref().err().cloned();
+    pipe.store(result).await;
+
+    match error {
+        Some(error) => Flow::Fail(Reason::new(error)),
+        None => Flow::Continue,
+    }
+}
+
+async fn settle_each<I: Clone + Send + Sync + 'static>(
+    items: Vec<I>,
+    parent: PipeContent,
+    body: PipelineDef<I>,
+    settings: Settings,
+    limit: usize,
+) -> Flow {
+    let run = |item: I| {
+        let (parent, body, settings) = (&parent, &body, settings.clone());
+        async move {
+            let content = parent.fork().await;
+            content.store(item).await;
+            body.run_on(Pipeline::from_content(content, settings).await)
+                .await
+        }
+    };
+
+    // Once a run stops, no item is started anymore. The runs already
+    // started are awaited, so that their finalizers run
+    let mut items = items.into_iter();
+    let mut runs = FuturesOrdered::new();
+    let mut stopped = None;
+    let mut results = Vec::new();
+    loop {
+        while stopped.is_none() && runs.len() < limit {
+            match items.next() {
+                Some(item) => runs.push_back(run(item)),
+                None => break,
+            }
+        }
+
+        let Some(mut finished) = runs.next().await else {
+            break;
+        };
+        finished.hand_up_compensations(&parent);
+        if stopped.is_some() {
+            continue;
+        }
+        if finished.outcome().is_completed() {
+            results.push(finished.deliver().await);
+        } else {
+            stopped = Some(Flow::from(finished.outcome()));
+        }
+    }
+
+    if let Some(flow) = stopped {
+        return flow;
+    }
+
+    parent.store(results).await;
+    Flow::Continue
+}
+
+#[async_trait]
+pub trait FamaPipe<Args, O> {
+    /// Where a pipe logic resides
+    async fn receive_pipe_content(&self, args: Args) -> O;
+
+    /// The name the execution log, `PipePanic` and the tracing spans
+    /// use for this pipe. Defaults to the type name
+    fn name(&self) -> String {
+        type_name::<Self>().to_string()
+    }
+
+    /// A short text about what the pipe does
+    fn description(&self) -> Option<String> {
+        None
+    }
+
+    /// Labels to group or filter pipes by
+    fn tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Wraps the type in a Box
+    fn to_pipe(self) -> Box<Self>
+    where
+        Self: Sized,
+    {
+        Box::new(self)
+    }
+}
+
+pub trait PipeFnHandler<Args, O>: Send + Sync + 'static {
+    type Future: Future<Output = O> + Send;
+
+    fn pipe_fn_handle(&mut self, args: Args) -> Self::Future;
+}
+
+impl<Func, Fut, O> PipeFnHandler<(), O> for Func
+where
+    Func: Send + Sync + FnMut() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = O> + Send,
+{
+    type Future = Fut;
+    fn pipe_fn_handle(&mut self, _: ()) -> Self::Future {
+        (self)()
+    }
+}
+
+impl<Func, Arg1, Fut, O> PipeFnHandler<(Arg1,), O> for Func
+where
+    Func: Send + Sync + FnMut(Arg1) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = O> + Send,
+{
+    type Future = Fut;
+    fn pipe_fn_handle(&mut self, (c,): (Arg1,)) -> Self::Future {
+        (self)(c)
+    }
+}
+
+macro_rules! pipe_func{
+    ($($T: ident),*) => {
+        impl<Func, $($T),+, Fut, O> PipeFnHandler <($($T),+), O> for Func
+         where Func: FnMut($($T),+) -> Fut + Send + Sync + 'static,
+         Fut: Future<Output = O> + Send,
+        {
+            type Future = Fut;
+
+            #[allow(non_snake_case)]
+            fn pipe_fn_handle(&mut self, ($($T),+): ($($T),+)) -> Self::Future {
+                (self)($($T),+)
             }
         }
     };
 }
 
-pipe_func! {Arg1, Arg2}
-pipe_func! {Arg1, Arg2, Arg3}
-pipe_func! {Arg1, Arg2, Arg3, Arg4}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15, Arg16}
-pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15, Arg16, Arg17}
+pipe_func! {Arg1, Arg2}
+pipe_func! {Arg1, Arg2, Arg3}
+pipe_func! {Arg1, Arg2, Arg3, Arg4}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15, Arg16}
+pipe_func! {Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15, Arg16, Arg17}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct AddOne;
+    #[async_trait]
+    impl FamaPipe<i32, i32> for AddOne {
+        async fn receive_pipe_content(&self, num: i32) -> i32 {
+            num + 1
+        }
+    }
+
+    struct AddTwo;
+    #[async_trait]
+    impl FamaPipe<i32, i32> for AddTwo {
+        async fn receive_pipe_content(&self, num: i32) -> i32 {
+            num + 2
+        }
+    }
+
+    struct StoreAddOne;
+    #[async_trait]
+    impl FamaPipe<(i32, PipeContent), ()> for StoreAddOne {
+        async fn receive_pipe_content(&self, (num, pipe): (i32, PipeContent)) {
+            pipe.store(num + 1).await;
+        }
+    }
+
+    struct StoreAddTwo;
+    #[async_trait]
+    impl FamaPipe<(i32, PipeContent), ()> for StoreAddTwo {
+        async fn receive_pipe_content(&self, (num, pipe): (i32, PipeContent)) {
+            pipe.store(num + 2).await;
+        }
+    }
+
+    struct ValidateCount;
+    #[async_trait]
+    impl FamaPipe<i32, bool> for ValidateCount {
+        async fn receive_pipe_content(&self, num: i32) -> bool {
+            num >= 6
+        }
+    }
+
+    #[tokio::test]
+    async fn test_through() {
+        let result = Pipeline::pass(0)
+            .await
+            .through(StoreAddOne)
+            .await
+            .through(StoreAddOne)
+            .await
+            .through(StoreAddTwo)
+            .await
+            .through(StoreAddTwo)
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(result, 6);
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        let result = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddTwo)
+            .await
+            .store(AddTwo)
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(result, 6);
+    }
+
+    #[tokio::test]
+    async fn test_next() {
+        let result = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddTwo)
+            .await
+            .next(ValidateCount)
+            .await
+            .store(AddTwo)
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(result, 4);
+
+        let result = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddTwo)
+            .await
+            .store(AddTwo)
+            .await
+            .store(AddTwo)
+            .await
+            .next(ValidateCount)
+            .await
+            .store(AddTwo)
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(result, 10);
+    }
+
+    #[tokio::test]
+    async fn test_through_fn1() {
+        let result: bool = Pipeline::pass(33)
+            .await
+            .through_fn(|num: i32, pipe: PipeContent| async move {
+                pipe.store(num + 2).await;
+            })
+            .await
+            .through_fn(|num: i32, pipe: PipeContent| async move {
+                pipe.store(num == 35).await;
+            })
+            .await
+            .deliver_as()
+            .await;
+
+        assert!(result);
+    }
+
+    #[tokio::test]
+    async fn test_next_fn() {
+        let result: bool = Pipeline::pass(33)
+            .await
+            .next_fn(|num: i32, pipe: PipeContent| async move {
+                pipe.store(num + 2).await;
+                true
+            })
+            .await
+            .next_fn(|num: i32| async move { num != 35 })
+            .await
+            .next_fn(|num: i32| async move { num == 35 })
+            .await
+            .confirm();
+
+        assert!(!result);
+    }
+
+    #[tokio::test]
+    async fn test_store_fn() {
+        let total = Pipeline::pass(0)
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await
+            .store_fn(|num: i32| async move { num + 4 })
+            .await
+            .store_fn(|num: i32| async move { num * 5 })
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(total, 25);
+    }
+
+    #[tokio::test]
+    async fn test_store2_fn() {
+        let total = Pipeline::<i32>::pass(0)
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await
+            .store_fn(|num: i32| async move { num + 4 })
+            .await
+            .store_fn(|num: i32| async move { num * 5 })
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(total, 25);
+    }
+
+    #[tokio::test]
+    async fn test_some_flow_fn() {
+        let result1 = Pipeline::pass(0)
+            .await
+            .some_fn(|n: i32| async move { if n > 10 { Some(n) } else { None } })
+            .await
+            .deliver_as::<Option<i32>>()
+            .await;
+
+        assert!(result1.is_none());
+
+        let result2 = Pipeline::pass(100)
+            .await
+            .some_fn(|n: i32| async move { if n > 10 { Some(n) } else { None } })
+            .await
+            .deliver_as::<Option<i32>>()
+            .await;
+
+        assert!(result2.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_some_flow() {
+        struct SomeI32;
+        #[async_trait::async_trait]
+        impl FamaPipe<i32, Option<i32>> for SomeI32 {
+            async fn receive_pipe_content(&self, n: i32) -> Option<i32> {
+                if n > 10 { Some(n) } else { None }
+            }
+        }
+        let result1 = Pipeline::pass(0)
+            .await
+            .some(SomeI32)
+            .await
+            .deliver_as::<Option<i32>>()
+            .await;
+
+        assert!(result1.is_none());
+
+        let result2 = Pipeline::pass(100)
+            .await
+            .some(SomeI32)
+            .await
+            .deliver_as::<Option<i32>>()
+            .await;
+
+        assert!(result2.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_result_flow_fn() {
+        let result1 = Pipeline::pass(0)
+            .await
+            .ok_fn(|n: i32| async move { if n > 10 { Ok::<i32, ()>(n) } else { Err(()) } })
+            .await
+            .deliver_as::<Result<i32, ()>>()
+            .await;
+
+        assert!(result1.is_err());
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let result2 = Pipeline::pass(100)
+            .await
+            .ok_fn(|n: i32| async move { if n > 10 { Ok::<i32, ()>(n) } else { Err(()) } })
+            .await
+            .deliver_as::<Result<i32, ()>>()
+            .await;
 
-    struct AddOne;
-    #[async_trait]
-    impl FamaPipe<i32, i32> for AddOne {
-        async fn receive_pipe_content(&self, num: i32) -> i32 {
-            num + 1
+        assert!(result2.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_result_flow() {
+        struct SomeI32;
+        #[async_trait::async_trait]
+        impl FamaPipe<i32, Result<i32, ()>> for SomeI32 {
+            async fn receive_pipe_content(&self, n: i32) -> Result<i32, ()> {
+                if n > 10 { Ok(n) } else { Err(()) }
+            }
         }
+        let result1 = Pipeline::pass(0)
+            .await
+            .ok(SomeI32)
+            .await
+            .deliver_as::<Result<i32, ()>>()
+            .await;
+
+        assert!(result1.is_err());
+
+        let result2 = Pipeline::pass(100)
+            .await
+            .ok(SomeI32)
+            .await
+            .deliver_as::<Result<i32, ()>>()
+            .await;
+
+        assert!(result2.is_ok());
     }
 
-    struct AddTwo;
-    #[async_trait]
-    impl FamaPipe<i32, i32> for AddTwo {
-        async fn receive_pipe_content(&self, num: i32) -> i32 {
-            num + 2
+    #[tokio::test]
+    async fn test_deliver_as() {
+        let result: bool = Pipeline::pass(0)
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await
+            .store_fn(|num: i32| async move { num + 4 })
+            .await
+            .store_fn(|num: i32| async move { num * 5 })
+            .await
+            .store_fn(|num: i32| async move { num == 25 })
+            .await
+            .deliver_as()
+            .await;
+
+        assert!(result);
+    }
+
+    #[tokio::test]
+    async fn test_outcome_completed() {
+        let pipeline = Pipeline::pass(0).await.store(AddOne).await;
+
+        assert!(pipeline.outcome().is_completed());
+        assert_eq!(pipeline.outcome().at_pipe(), None);
+    }
+
+    #[tokio::test]
+    async fn test_outcome_stopped() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .next(ValidateCount)
+            .await
+            .store(AddOne)
+            .await;
+
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 1,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .some_fn(|_: i32| async { None::<i32> })
+            .await;
+
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 0,
+                reason: StopReason::ReturnedNone
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_outcome_stopped_with_reason() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .through_fn(|pipe: PipeContent| async move {
+                pipe.stop_the_flow_with("count is too low").await;
+            })
+            .await;
+
+        match pipeline.outcome() {
+            FlowOutcome::Stopped { at_pipe, reason } => {
+                assert_eq!(*at_pipe, 1);
+                assert_eq!(reason.to_string(), "count is too low");
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outcome_failed() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct DbError(&'static str);
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .ok_fn(|n: i32| async move { Ok::<i32, DbError>(n) })
+            .await
+            .ok_fn(|_: i32| async move { Err::<i32, DbError>(DbError("connection lost")) })
+            .await
+            .store(AddOne)
+            .await;
+
+        match pipeline.outcome() {
+            FlowOutcome::Failed { at_pipe, error } => {
+                assert_eq!(*at_pipe, 1);
+                assert_eq!(
+                    error.downcast_ref::<DbError>(),
+                    Some(&DbError("connection lost"))
+                );
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execution_log() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .next(ValidateCount)
+            .await
+            .store(AddOne)
+            .await
+            .store(AddOne)
+            .await;
+
+        let log = pipeline.execution_log();
+        let statuses: Vec<PipeStatus> = log.iter().map(|r| r.status).collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                PipeStatus::Ran,
+                PipeStatus::Stopped,
+                PipeStatus::Skipped,
+                PipeStatus::Skipped
+            ]
+        );
+        assert_eq!(log.stopped_at().map(|r| r.index), Some(1));
+        assert_eq!(log.ran().count(), 2);
+        assert_eq!(log.skipped().count(), 2);
+        assert!(!pipeline.confirm());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_when_last_pipe_stops() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .store(AddOne)
+            .await
+            .next(ValidateCount)
+            .await;
+
+        assert!(!pipeline.confirm());
+
+        let pipeline = Pipeline::pass(10).await.next(ValidateCount).await;
+
+        assert!(pipeline.confirm());
+        assert!(pipeline.execution_log().went_through_all());
+    }
+
+    #[tokio::test]
+    async fn test_pipe_names() {
+        struct ChargeCard;
+        #[async_trait]
+        impl FamaPipe<i32, ()> for ChargeCard {
+            async fn receive_pipe_content(&self, _: i32) {
+                panic!("card declined");
+            }
+
+            fn name(&self) -> String {
+                "charge-card".to_string()
+            }
+
+            fn tags(&self) -> Vec<String> {
+                vec!["payment".to_string()]
+            }
+        }
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .catch_panics()
+            .store(AddOne)
+            .await
+            .named("double")
+            .describe("doubles the number")
+            .tag("math")
+            .store_fn(|num: i32| async move { num * 2 })
+            .await
+            .through(ChargeCard)
+            .await;
+
+        let log = pipeline.execution_log();
+        assert_eq!(log[0].name.as_deref(), Some(type_name::<AddOne>()));
+        assert_eq!(
+            log.by_name("double").and_then(|r| r.description.as_deref()),
+            Some("doubles the number")
+        );
+        assert_eq!(
+            log.tagged("payment").map(|r| r.index).collect::<Vec<_>>(),
+            [2]
+        );
+
+        let panic = pipeline.panicked().unwrap();
+        assert_eq!(panic.name.as_deref(), Some("charge-card"));
+        assert_eq!(
+            panic.to_string(),
+            "pipe 2 (charge-card) panicked: card declined"
+        );
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .when_fn(|| async { true }, |p| async { p })
+            .await
+            .switch_fn(|| async { 1 })
+            .await
+            .named("loop")
+            .repeat_while_fn(
+                |num: i32| async move { num < 2 },
+                5,
+                |p| async { p.store(AddOne).await },
+            )
+            .await
+            .through_pipeline(PipelineDef::new())
+            .await;
+
+        let names: Vec<_> = pipeline
+            .execution_log()
+            .iter()
+            .map(|r| r.name.clone().unwrap_or_default())
+            .collect();
+        assert!(names[0].contains("test_pipe_names"));
+        assert!(names[1].contains("test_pipe_names"));
+        assert_eq!(names[2], "loop");
+        assert_eq!(names[4], "loop");
+        assert_eq!(names[7], type_name::<PipelineDef<i32>>());
+        assert_eq!(pipeline.name(), "i32");
+        assert_eq!(pipeline.with_name("checkout").name(), "checkout");
+    }
+
+    #[tokio::test]
+    async fn test_catch_panics() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .catch_panics()
+            .store(AddOne)
+            .await
+            .through_fn(|num: i32| async move {
+                if num > 0 {
+                    panic!("number is too big: {}", num);
+                }
+            })
+            .await
+            .store(AddOne)
+            .await;
+
+        let panic = pipeline.panicked().expect("the pipe should have panicked");
+
+        assert_eq!(panic.index, 1);
+        assert_eq!(panic.message, "number is too big: 1");
+        assert!(pipeline.outcome().is_failed());
+        assert_eq!(pipeline.deliver().await, 1);
+        assert_eq!(
+            pipeline.execution_log().stopped_at().map(|r| r.index),
+            Some(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_isolated_pipe() {
+        struct Explode;
+        #[async_trait]
+        impl FamaPipe<(), ()> for Explode {
+            async fn receive_pipe_content(&self, _: ()) {
+                panic!("boom");
+            }
+        }
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .isolated()
+            .through(Explode)
+            .await
+            .store(AddOne)
+            .await;
+
+        assert_eq!(
+            pipeline.panicked().map(|p| p.message.as_str()),
+            Some("boom")
+        );
+        assert_eq!(pipeline.deliver().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_isolated_only_applies_to_next_pipe() {
+        let handle = tokio::spawn(async {
+            Pipeline::pass(0)
+                .await
+                .isolated()
+                .store(AddOne)
+                .await
+                .through_fn(|| async { panic!("not isolated") })
+                .await
+        });
+
+        assert!(handle.await.is_err_and(|e| e.is_panic()));
+    }
+
+    #[tokio::test]
+    async fn test_pipe_timeout() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .timeout(Duration::from_millis(10))
+            .store(AddOne)
+            .await
+            .timeout(Duration::from_millis(10))
+            .through_fn(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            })
+            .await
+            .store(AddOne)
+            .await;
+
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::TimedOut { at_pipe: 1 }
+        ));
+        assert_eq!(pipeline.deliver().await, 1);
+        assert_eq!(pipeline.execution_log().skipped().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .with_deadline(Instant::now() + Duration::from_millis(20))
+            .store(AddOne)
+            .await
+            .through_fn(|| async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+            })
+            .await
+            .store(AddOne)
+            .await;
+
+        assert!(pipeline.outcome().is_timed_out());
+        assert_eq!(pipeline.outcome().at_pipe(), Some(1));
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .with_deadline(Instant::now())
+            .store(AddOne)
+            .await;
+
+        assert_eq!(pipeline.outcome().at_pipe(), Some(0));
+        assert_eq!(pipeline.deliver().await, 0);
+        assert_eq!(pipeline.execution_log()[0].status, PipeStatus::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let pipeline = Pipeline::pass(0)
+            .await
+            .retry(RetryPolicy::fixed(Duration::from_millis(1)).max_attempts(5))
+            .ok_fn(|num: i32, pipe: PipeContent| async move {
+                // each attempt sees the value stored by the previous one
+                pipe.store(num + 1).await;
+                if num < 2 { Err("not yet") } else { Ok(num) }
+            })
+            .await;
+
+        assert!(pipeline.outcome().is_completed());
+        assert_eq!(pipeline.deliver().await, 3);
+        assert_eq!(pipeline.execution_log()[0].attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        struct AlwaysFail;
+        #[async_trait]
+        impl FamaPipe<(), Result<(), String>> for AlwaysFail {
+            async fn receive_pipe_content(&self, _: ()) -> Result<(), String> {
+                Err("down".to_string())
+            }
         }
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .retry(RetryPolicy::exponential(Duration::from_millis(1)).max_attempts(4))
+            .ok(AlwaysFail)
+            .await
+            .store(AddOne)
+            .await;
+
+        assert!(pipeline.outcome().is_failed());
+        assert_eq!(pipeline.execution_log()[0].attempts, 4);
+        assert_eq!(pipeline.execution_log()[1].attempts, 0);
+
+        let pipeline = Pipeline::pass(0)
+            .await
+            .retry(
+                RetryPolicy::fixed(Duration::ZERO)
+                    .retry_if(|error: &String| error.as_str() == "busy"),
+            )
+            .ok(AlwaysFail)
+            .await;
+
+        assert_eq!(pipeline.execution_log()[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_when_fn() {
+        let pipeline = Pipeline::pass(1)
+            .await
+            .when_fn(
+                |num: i32| async move { num > 0 },
+                |p| async { p.store(AddOne).await.store(AddTwo).await },
+            )
+            .await
+            .otherwise(|p| async { p.store_fn(|_: i32| async { -1 }).await })
+            .await
+            .store(AddOne)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 5);
+        assert_eq!(pipeline.execution_log().len(), 4);
     }
 
-    struct StoreAddOne;
-    #[async_trait]
-    impl FamaPipe<(i32, PipeContent), ()> for StoreAddOne {
-        async fn receive_pipe_content(&self, (num, pipe): (i32, PipeContent)) {
-            pipe.store(num + 1).await;
-        }
+    #[tokio::test]
+    async fn test_unless_fn_and_otherwise() {
+        let run = |num: i32| async move {
+            Pipeline::pass(num)
+                .await
+                .unless_fn(
+                    |num: i32| async move { num > 0 },
+                    |p| async { p.store_fn(|_: i32| async { 0 }).await },
+                )
+                .await
+                .otherwise(|p| async { p.store(AddTwo).await })
+                .await
+                .deliver()
+                .await
+        };
+
+        assert_eq!(run(-5).await, 0);
+        assert_eq!(run(5).await, 7);
     }
 
-    struct StoreAddTwo;
-    #[async_trait]
-    impl FamaPipe<(i32, PipeContent), ()> for StoreAddTwo {
-        async fn receive_pipe_content(&self, (num, pipe): (i32, PipeContent)) {
-            pipe.store(num + 2).await;
-        }
+    #[tokio::test]
+    async fn test_stop_inside_branch() {
+        let pipeline = Pipeline::pass(1)
+            .await
+            .when_fn(
+                || async { true },
+                |p| async { p.next(ValidateCount).await.store(AddOne).await },
+            )
+            .await
+            .otherwise(|p| async { p.store(AddTwo).await })
+            .await
+            .store(AddOne)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 1);
+        assert_eq!(pipeline.outcome().at_pipe(), Some(1));
     }
 
-    struct ValidateCount;
-    #[async_trait]
-    impl FamaPipe<i32, bool> for ValidateCount {
-        async fn receive_pipe_content(&self, num: i32) -> bool {
-            num >= 6
-        }
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Command {
+        Increment,
+        Double,
+        Reset,
     }
 
-    #[tokio::test]
-    async fn test_through() {
-        let result = Pipeline::pass(0)
+    async fn run_command(command: Command) -> Pipeline<i32> {
+        Pipeline::pass(5)
             .await
-            .through(StoreAddOne)
+            .switch_fn(move || async move { command })
             .await
-            .through(StoreAddOne)
+            .case(Command::Increment, |p| async { p.store(AddOne).await })
             .await
-            .through(StoreAddTwo)
+            .case(Command::Double, |p| async {
+                p.store_fn(|num: i32| async move { num * 2 })
+                    .await
+                    .store(AddOne)
+                    .await
+            })
             .await
-            .through(StoreAddTwo)
+            .default(|p| async { p.store_fn(|| async { 0 }).await })
             .await
-            .deliver()
-            .await;
+    }
 
-        assert_eq!(result, 6);
+    #[tokio::test]
+    async fn test_switch_fn() {
+        let pipeline = run_command(Command::Double).await;
+        assert_eq!(pipeline.deliver().await, 11);
+        assert_eq!(pipeline.execution_log().len(), 3);
+
+        assert_eq!(run_command(Command::Increment).await.deliver().await, 6);
+        assert_eq!(run_command(Command::Reset).await.deliver().await, 0);
     }
 
     #[tokio::test]
-    async fn test_store() {
-        let result = Pipeline::pass(0)
+    async fn test_switch_fn_without_match() {
+        let pipeline = Pipeline::pass(5)
             .await
-            .store(AddOne)
+            .switch_fn(|num: i32| async move { num.to_string() })
             .await
-            .store(AddOne)
+            .case("1".to_string(), |p| async { p.store(AddOne).await })
             .await
             .store(AddTwo)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 7);
+        assert!(pipeline.confirm());
+    }
+
+    #[tokio::test]
+    async fn test_case_key_mismatch() {
+        let pipeline = Pipeline::pass(5)
             .await
-            .store(AddTwo)
+            .switch_fn(|num: i32| async move { num.to_string() })
             .await
-            .deliver()
+            .case("5", |p| async { p.store(AddOne).await })
+            .await
+            .default(|p| async { p.store(AddTwo).await })
             .await;
 
-        assert_eq!(result, 6);
+        assert_eq!(pipeline.deliver().await, 5);
+        let FlowOutcome::Failed { at_pipe, error } = pipeline.outcome() else {
+            panic!("the flow did not fail");
+        };
+        assert_eq!(*at_pipe, 0);
+        assert_eq!(
+            error.downcast_ref::<CaseKeyMismatch>(),
+            Some(&CaseKeyMismatch {
+                selected: "alloc::string::String",
+                case: "&str",
+            })
+        );
     }
 
     #[tokio::test]
-    async fn test_next() {
-        let result = Pipeline::pass(0)
+    async fn test_repeat_while_fn() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .store(AddOne)
+            .repeat_while_fn(
+                |num: i32| async move { num < 6 },
+                10,
+                |p| async { p.store(AddTwo).await },
+            )
             .await
             .store(AddOne)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 8);
+        assert!(pipeline.confirm());
+    }
+
+    #[tokio::test]
+    async fn test_repeat_while_fn_limit() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .store(AddTwo)
-            .await
-            .next(ValidateCount)
+            .repeat_while_fn(|| async { true }, 3, |p| async { p.store(AddOne).await })
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 4);
+        match pipeline.outcome() {
+            FlowOutcome::Failed { error, .. } => assert_eq!(
+                error.downcast_ref::<IterationLimit>(),
+                Some(&IterationLimit { max_iterations: 3 })
+            ),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_for_each_fn() {
+        let pipeline = Pipeline::pass(vec![3, 1, 2])
             .await
-            .store(AddTwo)
+            .pass_content(vec![3, 1, 2])
             .await
-            .deliver()
+            .concurrency(3)
+            .for_each_fn(
+                |items: Vec<i32>| async move { items },
+                PipelineDef::new()
+                    .through_fn(|num: i32| async move {
+                        tokio::time::sleep(Duration::from_millis(num as u64 * 10)).await;
+                    })
+                    .store(AddOne),
+            )
             .await;
 
-        assert_eq!(result, 4);
+        assert!(pipeline.confirm());
+        assert_eq!(pipeline.deliver().await, vec![4, 2, 3]);
+    }
 
-        let result = Pipeline::pass(0)
+    #[tokio::test]
+    async fn test_for_each_fn_stops() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let body_seen = seen.clone();
+        let pipeline = Pipeline::pass(vec![6, 1, 8])
             .await
-            .store(AddOne)
+            .for_each_fn(
+                |items: Vec<i32>| async move { items },
+                PipelineDef::new()
+                    .through_fn(move |num: i32| {
+                        let seen = body_seen.clone();
+                        async move { seen.lock().unwrap().push(num) }
+                    })
+                    .next(ValidateCount)
+                    .store(AddOne),
+            )
             .await
-            .store(AddOne)
+            .store_fn(|| async { vec![0] })
+            .await;
+
+        assert_eq!(pipeline.deliver().await, vec![6, 1, 8]);
+        assert_eq!(pipeline.outcome().at_pipe(), Some(0));
+        assert_eq!(*seen.lock().unwrap(), vec![6, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_for_each_fn_settles_started_runs() {
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let body_undone = undone.clone();
+        let pipeline = Pipeline::pass(vec![1, 2])
             .await
-            .store(AddTwo)
+            .for_each_fn(
+                |items: Vec<i32>| async move { items },
+                PipelineDef::new()
+                    .through_fn(|| async {})
+                    .compensate_fn(move |num: i32| {
+                        let undone = body_undone.clone();
+                        async move {
+                            undone.lock().unwrap().push(num);
+                            Ok::<(), String>(())
+                        }
+                    })
+                    .next_fn(|num: i32| async move { num < 2 }),
+            )
             .await
-            .store(AddTwo)
+            .finish()
+            .await;
+
+        assert!(pipeline.outcome().is_stopped());
+        assert_eq!(*undone.lock().unwrap(), vec![2, 1]);
+
+        let finalized = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let body_finalized = finalized.clone();
+        Pipeline::pass(vec![1, 2, 3, 4])
             .await
-            .store(AddTwo)
+            .concurrency(3)
+            .for_each_fn(
+                |items: Vec<i32>| async move { items },
+                PipelineDef::new()
+                    .next_fn(|num: i32| async move { num != 1 })
+                    .finally_fn(move |num: i32| {
+                        let finalized = body_finalized.clone();
+                        async move { finalized.lock().unwrap().push(num) }
+                    }),
+            )
+            .await;
+
+        let mut finalized = finalized.lock().unwrap().clone();
+        finalized.sort();
+        assert_eq!(finalized, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_through_pipeline() {
+        let child = PipelineDef::new().store(AddOne).through_fn(
+            |num: i32, label: String, pipe: PipeContent| async move {
+                pipe.export(format!("{} {}", label, num)).await;
+            },
+        );
+
+        let pipeline = Pipeline::pass(1)
             .await
-            .next(ValidateCount)
+            .store_fn(|| async { "total".to_string() })
+            .await
+            .through_pipeline(child)
             .await
             .store(AddTwo)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 3);
+        assert_eq!(pipeline.deliver_as::<String>().await, "total 2");
+        assert_eq!(pipeline.execution_log().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_through_pipeline_stops() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .deliver()
+            .through_pipeline(PipelineDef::new().next(ValidateCount))
+            .await
+            .store(AddOne)
             .await;
 
-        assert_eq!(result, 10);
+        assert_eq!(pipeline.deliver().await, 1);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 0,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
     }
 
     #[tokio::test]
-    async fn test_through_fn1() {
-        let result: bool = Pipeline::pass(33)
+    async fn test_compensate_fn() {
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (first, second) = (undone.clone(), undone.clone());
+
+        let pipeline = Pipeline::pass(1)
             .await
-            .through_fn(|num: i32, pipe: PipeContent| async move {
-                pipe.store(num + 2).await;
+            .store(AddOne)
+            .await
+            .compensate_fn(move |num: i32| {
+                let undone = first.clone();
+                async move {
+                    undone.lock().unwrap().push(num);
+                    Ok::<(), String>(())
+                }
             })
+            .store(AddTwo)
             .await
-            .through_fn(|num: i32, pipe: PipeContent| async move {
-                pipe.store(num == 35).await;
+            .compensate_fn(move |num: i32| {
+                let undone = second.clone();
+                async move {
+                    undone.lock().unwrap().push(num);
+                    Err::<(), _>("mail already sent".to_string())
+                }
             })
+            .next(ValidateCount)
             .await
-            .deliver_as()
+            .compensate_fn(|| async { Ok::<(), String>(()) })
+            .store(AddOne)
             .await;
 
-        assert_eq!(result, true);
+        assert!(undone.lock().unwrap().is_empty());
+        let pipeline = pipeline.finish().await;
+        assert_eq!(*undone.lock().unwrap(), vec![4, 4]);
+
+        let compensations = pipeline.compensations();
+        assert_eq!(compensations.len(), 2);
+        assert_eq!(compensations[0].pipe, 1);
+        assert_eq!(
+            compensations[0]
+                .result
+                .as_ref()
+                .unwrap_err()
+                .downcast_ref::<String>()
+                .map(String::as_str),
+            Some("mail already sent")
+        );
+        assert_eq!(compensations[1].pipe, 0);
+        assert!(compensations[1].succeeded());
     }
 
     #[tokio::test]
-    async fn test_next_fn() {
-        let result: bool = Pipeline::pass(33)
-            .await
-            .next_fn(|num: i32, pipe: PipeContent| async move {
-                pipe.store(num + 2).await;
-                true
-            })
-            .await
-            .next_fn(|num: i32| async move { num != 35 })
+    async fn test_no_compensation_when_completed() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .next_fn(|num: i32| async move { num == 35 })
+            .store(AddOne)
             .await
-            .confirm();
+            .compensate_fn(|| async { Ok::<(), String>(()) })
+            .finish()
+            .await;
 
-        assert_eq!(result, false);
+        assert!(pipeline.confirm());
+        assert!(pipeline.compensations().is_empty());
     }
 
     #[tokio::test]
-    async fn test_store_fn() {
-        let total = Pipeline::pass(0)
+    async fn test_no_compensation_after_recovery() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .store_fn(|num: i32| async move { num + 1 })
+            .through_fn(|| async {})
             .await
-            .store_fn(|num: i32| async move { num + 4 })
+            .compensate_fn(|| async { Ok::<(), String>(()) })
+            .ok_fn(|| async { Err::<(), _>("card declined".to_string()) })
             .await
-            .store_fn(|num: i32| async move { num * 5 })
+            .recover_fn(|| async { true })
             .await
-            .deliver()
+            .finish()
             .await;
 
-        assert_eq!(total, 25);
+        assert!(pipeline.outcome().is_completed());
+        assert!(pipeline.confirm());
+        assert!(pipeline.compensations().is_empty());
     }
 
     #[tokio::test]
-    async fn test_store2_fn() {
-        let total = Pipeline::<i32>::pass(0)
+    async fn test_recover_fn() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .store_fn(|num: i32| async move { num + 1 })
+            .recover_fn(|| async { true })
             .await
-            .store_fn(|num: i32| async move { num + 4 })
+            .ok_fn(|| async { Err::<i32, _>("timeout".to_string()) })
             .await
-            .store_fn(|num: i32| async move { num * 5 })
+            .store(AddOne)
             .await
-            .deliver()
+            .recover_fn(
+                |result: Result<i32, String>, outcome: FlowOutcome, pipe: PipeContent| async move {
+                    assert!(outcome.is_failed());
+                    assert_eq!(result.unwrap_err(), "timeout");
+                    pipe.store(10).await;
+                    true
+                },
+            )
+            .await
+            .store(AddOne)
             .await;
 
-        assert_eq!(total, 25);
+        assert_eq!(pipeline.deliver().await, 11);
+        assert!(pipeline.outcome().is_completed());
+        assert_eq!(pipeline.execution_log().len(), 4);
+        assert_eq!(pipeline.execution_log()[1].status, PipeStatus::Skipped);
     }
 
     #[tokio::test]
-    async fn test_some_flow_fn() {
-        let result1 = Pipeline::pass(0)
+    async fn test_recover_declined() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .some_fn(|n: i32| async move { if n > 10 { Some(n) } else { None } })
+            .next(ValidateCount)
             .await
-            .deliver_as::<Option<i32>>()
+            .recover_fn(
+                |reason: StopReason| async move { !matches!(reason, StopReason::ReturnedFalse) },
+            )
+            .await
+            .store(AddOne)
             .await;
 
-        assert_eq!(result1.is_some(), false);
+        assert_eq!(pipeline.deliver().await, 1);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 0,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
+        assert_eq!(pipeline.execution_log()[2].status, PipeStatus::Skipped);
+    }
 
-        let result2 = Pipeline::pass(100)
+    #[tokio::test]
+    async fn test_finally_fn() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (first, second) = (seen.clone(), seen.clone());
+
+        let pipeline = Pipeline::pass(1)
             .await
-            .some_fn(|n: i32| async move { if n > 10 { Some(n) } else { None } })
+            .next(ValidateCount)
             .await
-            .deliver_as::<Option<i32>>()
+            .finally_fn(move |outcome: FlowOutcome| {
+                let seen = first.clone();
+                async move { seen.lock().unwrap().push(outcome.at_pipe()) }
+            })
+            .await
+            .store(AddOne)
+            .await
+            .finally_fn(move |pipe: PipeContent| {
+                let seen = second.clone();
+                async move {
+                    pipe.store(0).await;
+                    seen.lock().unwrap().push(None);
+                }
+            })
             .await;
 
-        assert_eq!(result2.is_some(), true);
+        assert_eq!(*seen.lock().unwrap(), vec![Some(0), None]);
+        assert_eq!(pipeline.outcome().at_pipe(), Some(0));
+        assert!(pipeline.outcome().is_stopped());
+        assert_eq!(pipeline.execution_log()[1].status, PipeStatus::Ran);
+        assert_eq!(pipeline.execution_log()[2].status, PipeStatus::Skipped);
+        assert_eq!(pipeline.execution_log()[3].status, PipeStatus::Ran);
     }
 
     #[tokio::test]
-    async fn test_some_flow() {
-        struct SomeI32;
-        #[async_trait::async_trait]
-        impl FamaPipe<i32, Option<i32>> for SomeI32 {
-            async fn receive_pipe_content(&self, n: i32) -> Option<i32> {
-                if n > 10 { Some(n) } else { None }
-            }
-        }
-        let result1 = Pipeline::pass(0)
+    async fn test_finally_cannot_restart() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .some(SomeI32)
+            .next(ValidateCount)
             .await
-            .deliver_as::<Option<i32>>()
+            .finally_fn(|pipe: PipeContent| async move {
+                pipe.stop_the_flow_with("cleanup failed").await;
+            })
+            .await
+            .store(AddOne)
             .await;
 
-        assert_eq!(result1.is_some(), false);
+        assert_eq!(pipeline.deliver().await, 1);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 0,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
+    }
 
-        let result2 = Pipeline::pass(100)
+    #[tokio::test]
+    async fn test_finally_on_completed_flow() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .some(SomeI32)
+            .store(AddOne)
             .await
-            .deliver_as::<Option<i32>>()
+            .finally_fn(|outcome: FlowOutcome| async move { outcome.is_completed() })
             .await;
 
-        assert_eq!(result2.is_some(), true);
+        assert!(pipeline.confirm());
+        assert!(pipeline.outcome().is_completed());
     }
 
     #[tokio::test]
-    async fn test_result_flow_fn() {
-        let result1 = Pipeline::pass(0)
-            .await
-            .ok_fn(|n: i32| async move { if n > 10 { Ok::<i32, ()>(n) } else { Err(()) } })
+    async fn test_cancellation() {
+        let token = CancellationToken::new();
+        let pipeline = Pipeline::pass(1)
             .await
-            .deliver_as::<Result<i32, ()>>()
+            .with_cancellation(token.clone())
             .await;
 
-        assert_eq!(result1.is_err(), true);
+        let handle = tokio::spawn(async move {
+            pipeline
+                .store(AddOne)
+                .await
+                .through_fn(|token: CancellationToken| async move {
+                    token.cancelled().await;
+                })
+                .await
+                .store(AddOne)
+                .await
+                .finally_fn(|outcome: FlowOutcome| async move { outcome.is_cancelled() })
+                .await
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        token.cancel();
+
+        let pipeline = handle.await.unwrap();
+        assert_eq!(pipeline.deliver().await, 2);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Cancelled { at_pipe: 2 }
+        ));
+        assert_eq!(pipeline.execution_log().ran().count(), 3);
+        assert_eq!(pipeline.execution_log()[2].status, PipeStatus::Skipped);
+    }
 
-        let result2 = Pipeline::pass(100)
+    #[tokio::test]
+    async fn test_finally_keeps_the_stop_reason() {
+        let pipeline = Pipeline::pass(1)
             .await
-            .ok_fn(|n: i32| async move { if n > 10 { Ok::<i32, ()>(n) } else { Err(()) } })
+            .next_fn(|| async { false })
             .await
-            .deliver_as::<Result<i32, ()>>()
+            .finally_fn(|pipe: PipeContent| async move {
+                pipe.stop_the_flow_with("cleanup").await;
+            })
+            .await
+            .recover_fn(|reason: StopReason, pipe: PipeContent| async move {
+                pipe.store(matches!(reason, StopReason::ReturnedFalse))
+                    .await;
+                false
+            })
             .await;
 
-        assert_eq!(result2.is_ok(), true);
+        assert!(pipeline.deliver_as::<bool>().await);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Stopped {
+                at_pipe: 0,
+                reason: StopReason::ReturnedFalse
+            }
+        ));
     }
 
     #[tokio::test]
-    async fn test_result_flow() {
-        struct SomeI32;
-        #[async_trait::async_trait]
-        impl FamaPipe<i32, Result<i32, ()>> for SomeI32 {
-            async fn receive_pipe_content(&self, n: i32) -> Result<i32, ()> {
-                if n > 10 { Ok(n) } else { Err(()) }
+    async fn test_finally_runs_when_cancelled_or_intercepted() {
+        struct StopEverything;
+        #[async_trait]
+        impl crate::Interceptor for StopEverything {
+            async fn before(&self, _: &crate::PipeInfo, content: &PipeContent) {
+                content.stop_the_flow().await;
             }
         }
-        let result1 = Pipeline::pass(0)
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let pipeline = Pipeline::pass(1)
             .await
-            .ok(SomeI32)
+            .with_cancellation(token)
             .await
-            .deliver_as::<Result<i32, ()>>()
+            .finally_fn(|pipe: PipeContent| async move {
+                pipe.store("lock released".to_string()).await;
+            })
             .await;
 
-        assert_eq!(result1.is_err(), true);
+        assert!(matches!(
+            pipeline.outcome(),
+            FlowOutcome::Cancelled { at_pipe: 0 }
+        ));
+        assert_eq!(pipeline.deliver_as::<String>().await, "lock released");
 
-        let result2 = Pipeline::pass(100)
+        let token = CancellationToken::new();
+        token.cancel();
+        let pipeline = Pipeline::pass(1)
             .await
-            .ok(SomeI32)
+            .with_cancellation(token)
             .await
-            .deliver_as::<Result<i32, ()>>()
+            .through_fn(|| async { panic!("the pipe was called") })
+            .await;
+        assert_eq!(pipeline.execution_log()[0].status, PipeStatus::Skipped);
+        assert_eq!(pipeline.execution_log().ran().count(), 0);
+
+        let pipeline = Pipeline::pass(1)
+            .await
+            .intercept(StopEverything)
+            .finally_fn(|pipe: PipeContent| async move {
+                pipe.store("lock released".to_string()).await;
+            })
             .await;
 
-        assert_eq!(result2.is_ok(), true);
+        assert!(pipeline.outcome().is_stopped());
+        assert_eq!(pipeline.deliver_as::<String>().await, "lock released");
     }
 
     #[tokio::test]
-    async fn test_deliver_as() {
-        let result: bool = Pipeline::pass(0)
-            .await
-            .store_fn(|num: i32| async move { num + 1 })
+    async fn test_cancellation_reaches_branches() {
+        let pipeline = Pipeline::pass(1).await;
+        let token = pipeline.cancellation_token();
+
+        let pipeline = pipeline
+            .through_all(
+                Join::new()
+                    .through_fn(|token: CancellationToken| async move { token.cancel() })
+                    .store(AddOne),
+            )
+            .await;
+
+        assert!(token.is_cancelled());
+        assert!(pipeline.outcome().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_map_fn() {
+        struct Parse;
+        #[async_trait]
+        impl FamaPipe<String, i32> for Parse {
+            async fn receive_pipe_content(&self, text: String) -> i32 {
+                text.parse().unwrap_or_default()
+            }
+        }
+
+        let pipeline: Pipeline<i32> = Pipeline::pass(" 41 ".to_string())
             .await
-            .store_fn(|num: i32| async move { num + 4 })
+            .map_fn(|text: String| async move { text.trim().to_string() })
             .await
-            .store_fn(|num: i32| async move { num * 5 })
+            .map(Parse)
             .await
-            .store_fn(|num: i32| async move { num == 25 })
+            .store(AddOne)
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 42);
+        assert_eq!(pipeline.deliver_as::<String>().await, "41");
+        assert_eq!(pipeline.execution_log().len(), 3);
+
+        let def = crate::PipelineDef::new().store(AddTwo);
+        assert_eq!(def.run_on(pipeline).await.deliver().await, 44);
+    }
+
+    #[tokio::test]
+    async fn test_map_fn_after_stop() {
+        async fn stopped() -> Pipeline<String> {
+            Pipeline::pass(1)
+                .await
+                .store_fn(|| async { "stale".to_string() })
+                .await
+                .next(ValidateCount)
+                .await
+                .map_fn(|num: i32| async move { num.to_string() })
+                .await
+        }
+
+        let pipeline = stopped().await;
+        assert!(pipeline.try_to_deliver().await.is_none());
+        assert_eq!(pipeline.try_deliver_as::<String>().await.unwrap(), "stale");
+        assert!(pipeline.outcome().is_stopped());
+
+        let recovered = pipeline.recover_fn(|| async { true }).await;
+        assert!(recovered.outcome().is_completed());
+        assert!(recovered.try_to_deliver().await.is_none());
+        let outcome = recovered.deliver_result::<FlowOutcome>().await.unwrap_err();
+        assert_eq!(outcome.at_pipe(), Some(1));
+
+        let restored = stopped()
             .await
-            .deliver_as()
+            .recover_fn(|pipe: PipeContent| async move {
+                pipe.store("cached".to_string()).await;
+                true
+            })
             .await;
+        assert_eq!(restored.deliver().await, "cached");
 
-        assert_eq!(result, true);
+        let pipeline = Pipeline::pass(1)
+            .await
+            .map_fn(|num: i32, pipe: PipeContent| async move {
+                pipe.stop_the_flow().await;
+                num.to_string()
+            })
+            .await;
+        assert!(pipeline.outcome().is_stopped());
+        assert_eq!(pipeline.try_to_deliver().await.unwrap(), "1");
+        assert_eq!(pipeline.try_deliver_as::<String>().await.unwrap(), "1");
     }
 }
@@ -1,12 +1,11 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 
 use futures::future::BoxFuture;
 use tokio::sync::RwLock;
 
-use crate::{Pipeline, pipeline::PipeFnHandler};
+use crate::{Interceptor, Pipeline, PipelineDef, PipelineTrait};
 
-type PipeList<T> =
-    Arc<RwLock<Vec<Box<dyn FnMut(Pipeline<T>) -> BoxFuture<'static, Pipeline<T>> + Send + Sync>>>>;
+type PipeList<T> = Arc<RwLock<PipelineDef<T>>>;
 
 /// PipelineBuilder provides flexibility and extensibility to your pipelines
 ///
@@ -132,20 +131,31 @@ impl<T: Clone + Send + Sync + 'static> PipelineBuilder<T> {
     where
         F: FnMut(Pipeline<T>) -> BoxFuture<'static, Pipeline<T>> + Send + Sync + 'static,
     {
+        let callback = Mutex::new(callback);
         let mut lock = self.pipes.write().await;
-        lock.push(Box::new(callback));
+        *lock = std::mem::take(&mut *lock).pipe(move |pipeline| {
+            (callback.lock().unwrap_or_else(PoisonError::into_inner))(pipeline)
+        });
 
         self
     }
 
-    pub async fn build(&self, content: T) -> Pipeline<T> {
-        let mut pipeline = Pipeline::pass(content).await;
+    /// Runs `interceptor` around every pipe of the builder, including
+    /// the pipes registered before it. See `Interceptor`
+    pub async fn intercept<I: Interceptor>(&self, interceptor: I) -> &Self {
         let mut lock = self.pipes.write().await;
-        for pipe in lock.iter_mut() {
-            pipeline = pipe.pipe_fn_handle((pipeline,)).await;
-        }
+        *lock = std::mem::take(&mut *lock).intercept(interceptor);
+
+        self
+    }
+
+    /// Returns a snapshot of the pipes registered so far
+    pub async fn definition(&self) -> PipelineDef<T> {
+        self.pipes.read().await.clone()
+    }
 
-        pipeline
+    pub async fn build(&self, content: T) -> Pipeline<T> {
+        self.definition().await.run(content).await
     }
 }
 
@@ -157,6 +167,17 @@ impl<T: Clone + Send + Sync + 'static> Default for PipelineBuilder<T> {
     }
 }
 
+/// Gives the builder the single subject and batch methods of
+/// `PipelineTrait`, such as `deliver_many`
+#[busybody::async_trait]
+impl<T: Clone + Send + Sync + 'static> PipelineTrait for PipelineBuilder<T> {
+    type Content = T;
+
+    async fn handle_pipe(&self, pipeline: Pipeline<Self::Content>) -> Pipeline<Self::Content> {
+        self.definition().await.run_on(pipeline).await
+    }
+}
+
 #[busybody::async_trait]
 pub trait PipelineBuilderTrait: Clone + Send + Sync {
     /// Will be called the first time an instance of the builder is instantiated
@@ -243,4 +264,72 @@ mod test {
 
         assert_eq!(user_a.id, user_b.id);
     }
+
+    #[derive(Debug, Clone, Default)]
+    struct Invoice {
+        total: i32,
+    }
+
+    #[crate::async_trait]
+    impl PipelineBuilderTrait for Invoice {
+        async fn setup_pipeline_builder(builder: PipelineBuilder<Self>) -> PipelineBuilder<Self> {
+            builder
+                .register(|pipeline| {
+                    Box::pin(async {
+                        pipeline
+                            .store_fn(|invoice: Invoice| async move {
+                                Invoice {
+                                    total: invoice.total * 2,
+                                }
+                            })
+                            .await
+                            .through_fn(|invoice: Invoice, pipe: crate::PipeContent| async move {
+                                pipe.export(invoice.total).await;
+                            })
+                            .await
+                    })
+                })
+                .await;
+            builder
+        }
+    }
+
+    #[tokio::test]
+    async fn test_through_builder() {
+        let pipeline = Pipeline::pass(1)
+            .await
+            .store_fn(|| async { Invoice { total: 20 } })
+            .await
+            .through_builder::<Invoice>()
+            .await;
+
+        assert_eq!(pipeline.deliver().await, 40);
+        assert_eq!(pipeline.deliver_as::<Invoice>().await.total, 20);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_many() {
+        let builder = PipelineBuilder::<u16>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .next_fn(|row: u16| async move { row % 2 == 1 })
+                        .await
+                        .store_fn(|row: u16| async move { row * 2 })
+                        .await
+                })
+            })
+            .await;
+
+        let delivered = builder.deliver_many(vec![1, 2, 3], 3).await;
+        let rejected: Vec<u16> = delivered
+            .iter()
+            .filter(|d| d.outcome.is_stopped())
+            .map(|d| d.content)
+            .collect();
+
+        assert_eq!(rejected, vec![2]);
+        assert_eq!(delivered[2].content, 6);
+    }
 }
@@ -0,0 +1,1026 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{Stream, StreamExt, future::BoxFuture, stream::BoxStream};
+
+use crate::{
+    FamaPipe, Interceptor, Join, PipeContent, Pipeline, PipelineBuilderTrait, PipelineTrait,
+    RetryPolicy, StreamOptions,
+    pipeline::{Flow, PipeFnHandler, Settings},
+};
+
+type Step<T> = Arc<dyn Fn(Pipeline<T>) -> BoxFuture<'static, Pipeline<T>> + Send + Sync>;
+
+/// A reusable, content independent pipeline
+///
+/// `PipelineDef` records the pipes once and can then be run against
+/// as many contents as required. The definition is cheap to clone and
+/// can be stored in a static.
+///
+/// ```rust
+///# use std::sync::LazyLock;
+///# use fama::PipelineDef;
+///
+/// static DOUBLE_THEN_ADD_ONE: LazyLock<PipelineDef<i32>> = LazyLock::new(|| {
+///     PipelineDef::new()
+///         .store_fn(|num: i32| async move { num * 2 })
+///         .store_fn(|num: i32| async move { num + 1 })
+/// });
+///
+/// #[tokio::main]
+/// async fn main() {
+///     assert_eq!(DOUBLE_THEN_ADD_ONE.run(2).await.deliver().await, 5);
+///     assert_eq!(DOUBLE_THEN_ADD_ONE.run(20).await.deliver().await, 41);
+/// }
+/// ```
+pub struct PipelineDef<T: Clone + Send + Sync + 'static> {
+    steps: Vec<Step<T>>,
+    /// How many of the steps only set an option of the next pipe
+    options: usize,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> PipelineDef<T> {
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            options: 0,
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Appends a raw step. The step receives the pipeline and must return it
+    pub fn pipe<F>(mut self, step: F) -> Self
+    where
+        F: Fn(Pipeline<T>) -> BoxFuture<'static, Pipeline<T>> + Send + Sync + 'static,
+    {
+        self.steps.push(Arc::new(step));
+        self
+    }
+
+    /// Appends a step that only sets an option of the pipes that follow.
+    /// It is not counted as a pipe
+    fn option<F>(mut self, step: F) -> Self
+    where
+        F: Fn(Pipeline<T>) -> BoxFuture<'static, Pipeline<T>> + Send + Sync + 'static,
+    {
+        self.options += 1;
+        self.pipe(step)
+    }
+
+    /// Runs `interceptor` around every pipe of the definition, including
+    /// the pipes recorded before it. See `Interceptor`
+    pub fn intercept<I: Interceptor>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Catches panics from the pipes that follow. See `Pipeline::catch_panics`
+    pub fn catch_panics(self) -> Self {
+        self.option(|pipeline| Box::pin(async move { pipeline.catch_panics() }))
+    }
+
+    /// Catches a panic from the next pipe only. See `Pipeline::isolated`
+    pub fn isolated(self) -> Self {
+        self.option(|pipeline| Box::pin(async move { pipeline.isolated() }))
+    }
+
+    /// Limits how long the next pipe may run. See `Pipeline::timeout`
+    pub fn timeout(self, duration: Duration) -> Self {
+        self.option(move |pipeline| Box::pin(async move { pipeline.timeout(duration) }))
+    }
+
+    /// Retries the next pipe when it returns an `Err`. See `Pipeline::retry`
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        self.option(move |pipeline| {
+            let policy = policy.clone();
+            Box::pin(async move { pipeline.retry(policy) })
+        })
+    }
+
+    /// Names the next pipe. See `Pipeline::named`
+    pub fn named(self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.option(move |pipeline| {
+            let name = name.clone();
+            Box::pin(async move { pipeline.named(name) })
+        })
+    }
+
+    /// Describes the next pipe. See `Pipeline::describe`
+    pub fn describe(self, description: impl Into<String>) -> Self {
+        let description = description.into();
+        self.option(move |pipeline| {
+            let description = description.clone();
+            Box::pin(async move { pipeline.describe(description) })
+        })
+    }
+
+    /// Tags the next pipe. See `Pipeline::tag`
+    pub fn tag(self, tag: impl Into<String>) -> Self {
+        let tag = tag.into();
+        self.option(move |pipeline| {
+            let tag = tag.clone();
+            Box::pin(async move { pipeline.tag(tag) })
+        })
+    }
+
+    /// Lets the next `for_each_fn` run items concurrently.
+    /// See `Pipeline::concurrency`
+    pub fn concurrency(self, limit: usize) -> Self {
+        self.option(move |pipeline| Box::pin(async move { pipeline.concurrency(limit) }))
+    }
+
+    /// Records a closure or function pipe. See `Pipeline::through_fn`
+    pub fn through_fn<H, Args, O>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().through_fn(handler).await })
+        })
+    }
+
+    /// Records a closure or function pipe. See `Pipeline::next_fn`
+    pub fn next_fn<H, Args>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().next_fn(handler).await })
+        })
+    }
+
+    /// Records a closure or function pipe. See `Pipeline::store_fn`
+    pub fn store_fn<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().store_fn(handler).await })
+        })
+    }
+
+    /// Records a closure or function pipe. See `Pipeline::some_fn`
+    pub fn some_fn<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Option<O>>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().some_fn(handler).await })
+        })
+    }
+
+    /// Records a closure or function pipe. See `Pipeline::ok_fn`
+    pub fn ok_fn<H, Args, O, E>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<O, E>>,
+        Args: busybody::Resolver + Send + 'static,
+        O: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().ok_fn(handler).await })
+        })
+    }
+
+    /// Records a struct pipe. See `Pipeline::through`
+    pub fn through<H, Args, O>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+        O: Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.through(handler).await })
+        })
+    }
+
+    /// Records a struct pipe. See `Pipeline::next`
+    pub fn next<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.next(handler).await })
+        })
+    }
+
+    /// Records a struct pipe. See `Pipeline::store`
+    pub fn store<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.store(handler).await })
+        })
+    }
+
+    /// Records a struct pipe. See `Pipeline::some`
+    pub fn some<H, Args, O: Clone + Send + Sync + 'static>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Option<O>> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.some(handler).await })
+        })
+    }
+
+    /// Records a struct pipe. See `Pipeline::ok`
+    pub fn ok<H, Args, O, E>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Result<O, E>> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+        O: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.ok(handler).await })
+        })
+    }
+
+    /// Records a pipe that only runs when the flow has been stopped.
+    /// See `Pipeline::recover_fn`
+    pub fn recover_fn<H, Args>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().recover_fn(handler).await })
+        })
+    }
+
+    /// Records a struct pipe that only runs when the flow has been stopped.
+    /// See `Pipeline::recover`
+    pub fn recover<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.recover(handler).await })
+        })
+    }
+
+    /// Records a pipe that runs whether or not the flow has been stopped.
+    /// See `Pipeline::finally_fn`
+    pub fn finally_fn<H, Args, O>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().finally_fn(handler).await })
+        })
+    }
+
+    /// Records a struct pipe that runs whether or not the flow has been
+    /// stopped. See `Pipeline::finally`
+    pub fn finally<H, Args, O>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O> + Send + Sync + 'static,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let handler = SharedPipe(Arc::new(handler));
+        self.pipe(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.finally(handler).await })
+        })
+    }
+
+    /// Registers a handler that undoes the work of the previous pipe.
+    /// See `Pipeline::compensate_fn`
+    pub fn compensate_fn<H, Args, E>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<(), E>>,
+        Args: busybody::Resolver + Send + 'static,
+        E: Send + Sync + 'static,
+    {
+        let handler = SharedFn::new(handler);
+        self.option(move |pipeline| {
+            let handler = handler.clone();
+            Box::pin(async move { pipeline.compensate_fn(handler) })
+        })
+    }
+
+    /// Records a branch that runs when the predicate returns true.
+    /// See `Pipeline::when_fn`
+    pub fn when_fn<H, Args>(self, predicate: H, then: PipelineDef<T>) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let predicate = SharedFn::new(predicate);
+        self.pipe(move |pipeline| {
+            let predicate = predicate.clone();
+            let then = then.clone();
+            Box::pin(async move {
+                pipeline
+                    .name_pipe::<H>()
+                    .when_fn(predicate, |p| async move { then.run_on(p).await })
+                    .await
+            })
+        })
+    }
+
+    /// Records a branch that runs when the predicate returns false.
+    /// See `Pipeline::unless_fn`
+    pub fn unless_fn<H, Args>(self, predicate: H, then: PipelineDef<T>) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let predicate = SharedFn::new(predicate);
+        self.pipe(move |pipeline| {
+            let predicate = predicate.clone();
+            let then = then.clone();
+            Box::pin(async move {
+                pipeline
+                    .name_pipe::<H>()
+                    .unless_fn(predicate, |p| async move { then.run_on(p).await })
+                    .await
+            })
+        })
+    }
+
+    /// Records the branch for when the preceding `when_fn` or `unless_fn`
+    /// did not run its own. See `Pipeline::otherwise`
+    pub fn otherwise(self, then: PipelineDef<T>) -> Self {
+        self.pipe(move |pipeline| {
+            let then = then.clone();
+            Box::pin(async move {
+                pipeline
+                    .otherwise(|p| async move { then.run_on(p).await })
+                    .await
+            })
+        })
+    }
+
+    /// Records a loop that runs `body` while the condition returns true.
+    /// See `Pipeline::repeat_while_fn`
+    pub fn repeat_while_fn<H, Args>(
+        self,
+        condition: H,
+        max_iterations: usize,
+        body: PipelineDef<T>,
+    ) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + Send + 'static,
+    {
+        let condition = SharedFn::new(condition);
+        self.pipe(move |pipeline| {
+            let condition = condition.clone();
+            let body = body.clone();
+            Box::pin(async move {
+                pipeline
+                    .name_pipe::<H>()
+                    .repeat_while_fn(condition, max_iterations, |p| {
+                        let body = body.clone();
+                        async move { body.run_on(p).await }
+                    })
+                    .await
+            })
+        })
+    }
+
+    /// Records a loop that runs `body` once per item.
+    /// See `Pipeline::for_each_fn`
+    pub fn for_each_fn<H, Args, I>(self, items: H, body: PipelineDef<I>) -> Self
+    where
+        H: PipeFnHandler<Args, Vec<I>>,
+        Args: busybody::Resolver + Send + 'static,
+        I: Clone + Send + Sync + 'static,
+    {
+        let items = SharedFn::new(items);
+        self.pipe(move |pipeline| {
+            let items = items.clone();
+            let body = body.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().for_each_fn(items, body).await })
+        })
+    }
+
+    /// Records a selector for the `case` arms that follow.
+    /// See `Pipeline::switch_fn`
+    pub fn switch_fn<H, Args, K>(self, selector: H) -> Self
+    where
+        H: PipeFnHandler<Args, K>,
+        Args: busybody::Resolver + Send + 'static,
+        K: PartialEq + Send + Sync + 'static,
+    {
+        let selector = SharedFn::new(selector);
+        self.pipe(move |pipeline| {
+            let selector = selector.clone();
+            Box::pin(async move { pipeline.name_pipe::<H>().switch_fn(selector).await })
+        })
+    }
+
+    /// Records the arm that runs for `key`. See `Pipeline::case`
+    pub fn case<K>(self, key: K, then: PipelineDef<T>) -> Self
+    where
+        K: PartialEq + Clone + Send + Sync + 'static,
+    {
+        self.pipe(move |pipeline| {
+            let key = key.clone();
+            let then = then.clone();
+            Box::pin(async move {
+                pipeline
+                    .case(key, |p| async move { then.run_on(p).await })
+                    .await
+            })
+        })
+    }
+
+    /// Records the arm that runs when no `case` matched.
+    /// See `Pipeline::default`
+    pub fn default(self, then: PipelineDef<T>) -> Self {
+        self.pipe(move |pipeline| {
+            let then = then.clone();
+            Box::pin(async move {
+                pipeline
+                    .default(|p| async move { then.run_on(p).await })
+                    .await
+            })
+        })
+    }
+
+    /// Records a nested pipeline. See `Pipeline::through_pipeline`
+    pub fn through_pipeline(self, def: PipelineDef<T>) -> Self {
+        self.pipe(move |pipeline| {
+            let def = def.clone();
+            Box::pin(async move { pipeline.through_pipeline(def).await })
+        })
+    }
+
+    /// Records the pipeline registered for `U` as a nested pipeline.
+    /// See `Pipeline::through_builder`
+    pub fn through_builder<U: PipelineBuilderTrait + 'static>(self) -> Self {
+        self.pipe(|pipeline| Box::pin(async move { pipeline.through_builder::<U>().await }))
+    }
+
+    /// Records a set of concurrent branches. See `Pipeline::through_all`
+    pub fn through_all(self, join: Join<T>) -> Self {
+        self.pipe(move |pipeline| {
+            let join = join.clone();
+            Box::pin(async move { pipeline.through_all(join).await })
+        })
+    }
+
+    /// Returns the number of recorded pipes. Options of the next pipe,
+    /// such as `named` or `timeout`, and compensation handlers are not
+    /// counted
+    pub fn len(&self) -> usize {
+        self.steps.len() - self.options
+    }
+
+    /// Returns true when no pipe has been recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Passes the content through the recorded steps
+    pub async fn run(&self, content: T) -> Pipeline<T> {
+        self.run_on(Pipeline::pass(content).await)
+            .await
+            .finish()
+            .await
+    }
+
+    /// Passes each item of `input` through the recorded steps and yields
+    /// the pipelines as they finish. Every item gets its own pipeline, so
+    /// an item that stops, fails or panics does not affect the others.
+    /// Panics are caught and reported through `Pipeline::panicked`
+    ///
+    /// ```rust
+    ///# use fama::{PipelineDef, StreamOptions};
+    ///# use futures::StreamExt;
+    ///# #[tokio::main]
+    ///# async fn main() {
+    /// let def = PipelineDef::new()
+    ///     .next_fn(|num: i32| async move { num % 2 == 0 })
+    ///     .store_fn(|num: i32| async move { num * 10 });
+    ///
+    /// let pipelines: Vec<_> = def
+    ///     .run_stream(futures::stream::iter(1..=4), StreamOptions::new().concurrency(2))
+    ///     .collect()
+    ///     .await;
+    ///
+    /// let mut results = Vec::new();
+    /// for pipeline in &pipelines {
+    ///     results.push((pipeline.outcome().is_completed(), pipeline.deliver().await));
+    /// }
+    /// assert_eq!(results, vec![(false, 1), (true, 20), (false, 3), (true, 40)]);
+    ///# }
+    /// ```
+    pub fn run_stream<S>(&self, input: S, options: StreamOptions) -> BoxStream<'static, Pipeline<T>>
+    where
+        S: Stream<Item = T> + Send + 'static,
+    {
+        let (concurrency, ordered) = (options.concurrency, options.ordered);
+        let def = self.clone();
+        let runs = input.map(move |content| {
+            let def = def.clone();
+            let cancellation = options.cancellation.clone();
+            async move {
+                let mut pipeline = Pipeline::pass(content).await.catch_panics();
+                if let Some(token) = cancellation {
+                    pipeline = pipeline.with_cancellation(token).await;
+                }
+                def.run_on(pipeline).await.finish().await
+            }
+        });
+
+        if ordered {
+            runs.buffered(concurrency).boxed()
+        } else {
+            runs.buffer_unordered(concurrency).boxed()
+        }
+    }
+
+    /// Runs the steps on a copy of the parent content, then stores the
+    /// exported values into the parent
+    pub(crate) async fn run_nested(&self, parent: PipeContent, settings: Settings) -> Flow {
+        let mut child = self
+            .run_on(Pipeline::from_content(parent.fork().await, settings).await)
+            .await;
+        child.hand_up_compensations(&parent);
+
+        for entry in child.pipe_content().exported_entries() {
+            entry.value.store_into(&parent).await;
+        }
+
+        Flow::from(child.outcome())
+    }
+
+    /// Runs the recorded steps on an existing pipeline. The pipeline is
+    /// not finished: call `Pipeline::finish` once done with it, for its
+    /// compensations and metrics
+    pub async fn run_on(&self, mut pipeline: Pipeline<T>) -> Pipeline<T> {
+        // The interceptors of the definition only wrap its own steps
+        let start = pipeline.push_interceptors(&self.interceptors);
+        for step in &self.steps {
+            pipeline = step(pipeline).await;
+        }
+
+        pipeline.pop_interceptors(start, self.interceptors.len())
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Clone for PipelineDef<T> {
+    fn clone(&self) -> Self {
+        Self {
+            steps: self.steps.clone(),
+            options: self.options,
+            interceptors: self.interceptors.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for PipelineDef<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl<T: Clone + Send + Sync + 'static> PipelineTrait for PipelineDef<T> {
+    type Content = T;
+
+    async fn handle_pipe(&self, pipeline: Pipeline<Self::Content>) -> Pipeline<Self::Content> {
+        self.run_on(pipeline).await
+    }
+}
+
+/// Shares a struct pipe between the runs of a definition
+struct SharedPipe<H>(Arc<H>);
+
+impl<H> Clone for SharedPipe<H> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[async_trait]
+impl<H, Args, O> FamaPipe<Args, O> for SharedPipe<H>
+where
+    H: FamaPipe<Args, O> + Send + Sync,
+    Args: Send + 'static,
+{
+    async fn receive_pipe_content(&self, args: Args) -> O {
+        self.0.receive_pipe_content(args).await
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn description(&self) -> Option<String> {
+        self.0.description()
+    }
+
+    fn tags(&self) -> Vec<String> {
+        self.0.tags()
+    }
+}
+
+/// Shares a closure or function pipe between the runs of a definition.
+/// The lock is only held while the handler creates its future.
+struct SharedFn<H>(Arc<Mutex<H>>);
+
+impl<H> SharedFn<H> {
+    fn new(handler: H) -> Self {
+        Self(Arc::new(Mutex::new(handler)))
+    }
+}
+
+impl<H> Clone for SharedFn<H> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<H, Args, O> PipeFnHandler<Args, O> for SharedFn<H>
+where
+    H: PipeFnHandler<Args, O>,
+{
+    type Future = H::Future;
+
+    fn pipe_fn_handle(&mut self, args: Args) -> Self::Future {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pipe_fn_handle(args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FlowOutcome, PipeContent};
+
+    struct AddOne;
+    #[async_trait]
+    impl FamaPipe<i32, i32> for AddOne {
+        async fn receive_pipe_content(&self, num: i32) -> i32 {
+            num + 1
+        }
+    }
+
+    struct StopAtTen;
+    #[async_trait]
+    impl FamaPipe<i32, bool> for StopAtTen {
+        async fn receive_pipe_content(&self, num: i32) -> bool {
+            num < 10
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_many_contents() {
+        let def = PipelineDef::new()
+            .store(AddOne)
+            .store_fn(|num: i32| async move { num * 2 })
+            .through_fn(|num: i32, pipe: PipeContent| async move {
+                pipe.store(num + 1).await;
+            });
+
+        assert_eq!(def.len(), 3);
+        assert_eq!(def.run(1).await.deliver().await, 5);
+        assert_eq!(
+            PipelineDef::<i32>::new()
+                .named("double")
+                .timeout(Duration::from_secs(1))
+                .store_fn(|num: i32| async move { num * 2 })
+                .len(),
+            1
+        );
+        assert!(PipelineDef::<i32>::new().catch_panics().is_empty());
+        assert_eq!(def.run(10).await.deliver().await, 23);
+    }
+
+    #[tokio::test]
+    async fn test_clone_and_stop() {
+        let def = PipelineDef::new()
+            .store(AddOne)
+            .next(StopAtTen)
+            .store(AddOne);
+        let cloned = def.clone();
+
+        assert_eq!(def.run(1).await.deliver().await, 3);
+        assert_eq!(cloned.run(9).await.deliver().await, 10);
+        assert!(!cloned.run(9).await.confirm());
+    }
+
+    #[tokio::test]
+    async fn test_def_as_pipeline_trait() {
+        let def = PipelineDef::new()
+            .some_fn(|num: i32| async move { if num > 0 { Some(num) } else { None } })
+            .ok_fn(|num: i32| async move { Ok::<String, ()>(num.to_string()) });
+
+        assert_eq!(
+            def.deliver_as::<Result<String, ()>>(4).await,
+            Ok("4".to_string())
+        );
+        assert!(def.try_deliver_as::<Result<String, ()>>(0).await.is_none());
+
+        let log = def.execution_log(0).await;
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.stopped_at().map(|r| r.index), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_pipe_names() {
+        let def = PipelineDef::new()
+            .store(AddOne)
+            .named("double")
+            .store_fn(|num: i32| async move { num * 2 })
+            .through_fn(|| async {});
+
+        let log = def.execution_log(1).await;
+        let names: Vec<_> = log.iter().map(|r| r.name.clone().unwrap()).collect();
+        assert_eq!(names[0], std::any::type_name::<AddOne>());
+        assert_eq!(names[1], "double");
+        assert!(names[2].contains("test_pipe_names"));
+    }
+
+    #[tokio::test]
+    async fn test_catch_panics() {
+        let def = PipelineDef::new()
+            .catch_panics()
+            .store(AddOne)
+            .through_fn(|num: i32| async move { assert!(num < 5, "too big") });
+
+        assert!(def.confirm(1).await);
+
+        let pipeline = def.run(10).await;
+        assert_eq!(pipeline.panicked().map(|p| p.index), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_when_fn() {
+        let def = PipelineDef::new()
+            .when_fn(
+                |num: i32| async move { num % 2 == 0 },
+                PipelineDef::new().store_fn(|num: i32| async move { num / 2 }),
+            )
+            .otherwise(PipelineDef::new().store_fn(|num: i32| async move { num * 3 + 1 }))
+            .store(AddOne);
+
+        assert_eq!(def.run(10).await.deliver().await, 6);
+        assert_eq!(def.run(3).await.deliver().await, 11);
+    }
+
+    #[tokio::test]
+    async fn test_switch_fn() {
+        let def = PipelineDef::new()
+            .switch_fn(|num: i32| async move { num % 3 })
+            .case(
+                0,
+                PipelineDef::new().store_fn(|num: i32| async move { num / 3 }),
+            )
+            .case(1, PipelineDef::new().store(AddOne))
+            .default(PipelineDef::new().store_fn(|| async { 0 }));
+
+        assert_eq!(def.run(9).await.deliver().await, 3);
+        assert_eq!(def.run(10).await.deliver().await, 11);
+        assert_eq!(def.run(11).await.deliver().await, 0);
+        let pipeline = def.run(9).await;
+        let selector = pipeline.execution_log()[0].name.as_deref().unwrap();
+        assert!(selector.contains("test_switch_fn"));
+    }
+
+    #[tokio::test]
+    async fn test_loops() {
+        let def: PipelineDef<Vec<i32>> = PipelineDef::new()
+            .repeat_while_fn(
+                |items: Vec<i32>| async move { items.len() < 4 },
+                10,
+                PipelineDef::new().store_fn(|mut items: Vec<i32>| async move {
+                    items.push(items.len() as i32);
+                    items
+                }),
+            )
+            .concurrency(2)
+            .for_each_fn(
+                |items: Vec<i32>| async move { items },
+                PipelineDef::new().store(AddOne),
+            );
+
+        assert_eq!(def.run(vec![]).await.deliver().await, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_interceptors_stay_in_their_def() {
+        use crate::PipeInfo;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone, Default)]
+        struct Count(Arc<AtomicUsize>);
+        #[async_trait]
+        impl Interceptor for Count {
+            async fn before(&self, _: &PipeInfo, _: &PipeContent) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let count = Count::default();
+        let body = PipelineDef::new()
+            .intercept(count.clone())
+            .store(AddOne)
+            .store(AddOne)
+            .store(AddOne);
+        let def = PipelineDef::new()
+            .repeat_while_fn(|num: i32| async move { num < 6 }, 5, body)
+            .store(AddOne)
+            .store(AddOne);
+
+        assert_eq!(def.run(0).await.deliver().await, 8);
+        assert_eq!(count.0.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_compensate_fn() {
+        let def = PipelineDef::new()
+            .store(AddOne)
+            .compensate_fn(|pipe: PipeContent| async move {
+                pipe.store(0).await;
+                Ok::<(), String>(())
+            })
+            .next(StopAtTen);
+
+        let pipeline = def.run(20).await;
+        assert_eq!(pipeline.deliver().await, 0);
+        assert_eq!(pipeline.compensations().len(), 1);
+
+        let pipeline = def.run(1).await;
+        assert_eq!(pipeline.deliver().await, 2);
+        assert!(pipeline.compensations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nested_compensations_wait_for_the_parent() {
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let child_undone = undone.clone();
+        let child = PipelineDef::new()
+            .store(AddOne)
+            .compensate_fn(move |num: i32| {
+                let undone = child_undone.clone();
+                async move {
+                    undone.lock().unwrap().push(num);
+                    Ok::<(), String>(())
+                }
+            })
+            .next(StopAtTen);
+
+        let recovered = PipelineDef::new()
+            .through_pipeline(child.clone())
+            .recover_fn(|| async { true });
+        let pipeline = recovered.run(20).await;
+        assert!(pipeline.outcome().is_completed());
+        assert!(pipeline.compensations().is_empty());
+        assert!(undone.lock().unwrap().is_empty());
+
+        let pipeline = PipelineDef::new().through_pipeline(child).run(20).await;
+        assert!(pipeline.outcome().is_stopped());
+        assert_eq!(pipeline.compensations().len(), 1);
+        assert_eq!(pipeline.compensations()[0].pipe, 0);
+        assert_eq!(*undone.lock().unwrap(), vec![21]);
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        struct UseDefault;
+        #[async_trait]
+        impl FamaPipe<PipeContent, bool> for UseDefault {
+            async fn receive_pipe_content(&self, pipe: PipeContent) -> bool {
+                pipe.store(9).await;
+                true
+            }
+        }
+
+        let def = PipelineDef::new()
+            .next(StopAtTen)
+            .recover(UseDefault)
+            .store(AddOne);
+
+        assert_eq!(def.run(20).await.deliver().await, 10);
+        assert_eq!(def.run(1).await.deliver().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_finally() {
+        struct Release;
+        #[async_trait]
+        impl FamaPipe<(FlowOutcome, PipeContent), ()> for Release {
+            async fn receive_pipe_content(&self, (outcome, pipe): (FlowOutcome, PipeContent)) {
+                pipe.store(outcome.is_completed()).await;
+            }
+        }
+
+        let def = PipelineDef::new().next(StopAtTen).finally(Release);
+
+        assert!(def.run(1).await.deliver_as::<bool>().await);
+        assert!(!def.run(20).await.deliver_as::<bool>().await);
+    }
+
+    #[tokio::test]
+    async fn test_run_stream() {
+        let def = PipelineDef::new()
+            .through_fn(|num: i32| async move {
+                if num == 3 {
+                    panic!("bad record");
+                }
+            })
+            .store(AddOne);
+
+        let ordered: Vec<_> = def
+            .run_stream(
+                futures::stream::iter(1..=4),
+                StreamOptions::new().concurrency(4),
+            )
+            .collect()
+            .await;
+        let mut delivered = Vec::new();
+        for pipeline in &ordered {
+            delivered.push(pipeline.deliver().await);
+        }
+        assert_eq!(delivered, vec![2, 3, 3, 5]);
+        assert_eq!(
+            ordered[2].panicked().map(|p| p.message.as_str()),
+            Some("bad record")
+        );
+
+        // The first item is held until the second one was yielded
+        let release = Arc::new(tokio::sync::Notify::new());
+        let held = release.clone();
+        let def = PipelineDef::new()
+            .through_fn(move |num: i32| {
+                let held = held.clone();
+                async move {
+                    if num == 1 {
+                        held.notified().await;
+                    }
+                }
+            })
+            .store(AddOne);
+
+        let mut unordered = def.run_stream(
+            futures::stream::iter([1, 4]),
+            StreamOptions::new().concurrency(2).unordered(),
+        );
+        let first = unordered.next().await.unwrap();
+        release.notify_one();
+        let second = unordered.next().await.unwrap();
+        assert_eq!(first.deliver().await, 5);
+        assert_eq!(second.deliver().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_many() {
+        let def = PipelineDef::new().next(StopAtTen).store(AddOne);
+
+        let delivered = def.deliver_many(vec![1, 20, 3], 2).await;
+        let contents: Vec<i32> = delivered.iter().map(|d| d.content).collect();
+        assert_eq!(contents, vec![2, 20, 4]);
+        assert_eq!(delivered[1].outcome.at_pipe(), Some(0));
+
+        assert_eq!(def.confirm_many(vec![30, 4], 1).await, vec![false, true]);
+        assert_eq!(def.try_deliver_many(vec![5], 1).await[0].content, Some(6));
+    }
+}
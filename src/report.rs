@@ -0,0 +1,153 @@
+use std::{fmt::Display, time::Duration};
+
+use crate::{FlowOutcome, PipeRecord, PipeStatus};
+
+/// A summary of a pipeline run: each pipe with its timings and the
+/// values it stored, and how the flow ended
+///
+/// The `Display` rendering is short enough to paste into a bug report.
+///
+/// ```rust
+///# #[tokio::main]
+///# async fn main() {
+/// let pipeline = fama::Pipeline::pass(21)
+///     .await
+///     .named("double")
+///     .store_fn(|num: i32| async move { num * 2 })
+///     .await
+///     .next_fn(|num: i32| async move { num < 10 })
+///     .await;
+///
+/// let report = pipeline.report();
+/// assert_eq!(report.pipes[0].stored, ["i32"]);
+/// assert!(report.outcome.is_stopped());
+/// println!("{}", report);
+///# }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PipelineReport {
+    /// The type name of the pipeline content
+    pub content: &'static str,
+    pub outcome: FlowOutcome,
+    pub pipes: Vec<PipeRecord>,
+}
+
+impl PipelineReport {
+    pub(crate) fn new(content: &'static str, outcome: FlowOutcome, pipes: Vec<PipeRecord>) -> Self {
+        Self {
+            content,
+            outcome,
+            pipes,
+        }
+    }
+
+    /// Time between the start of the pipeline and the end of its last pipe
+    pub fn elapsed(&self) -> Duration {
+        self.pipes
+            .iter()
+            .map(|pipe| pipe.offset + pipe.duration)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Returns the pipe that took the longest
+    pub fn slowest(&self) -> Option<&PipeRecord> {
+        self.pipes.iter().max_by_key(|pipe| pipe.duration)
+    }
+}
+
+impl Display for PipelineReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pipeline<{}> ", self.content)?;
+        match &self.outcome {
+            FlowOutcome::Completed => write!(f, "completed")?,
+            FlowOutcome::Stopped { at_pipe, reason } => {
+                write!(f, "stopped at pipe {}: {}", at_pipe, reason)?
+            }
+            FlowOutcome::Failed { at_pipe, error } => {
+                write!(f, "failed at pipe {}: {}", at_pipe, error)?
+            }
+            FlowOutcome::TimedOut { at_pipe } => write!(f, "timed out at pipe {}", at_pipe)?,
+            FlowOutcome::Cancelled { at_pipe } => write!(f, "cancelled at pipe {}", at_pipe)?,
+        }
+        write!(f, " in {:?}", self.elapsed())?;
+
+        for pipe in &self.pipes {
+            let status = match pipe.status {
+                PipeStatus::Ran => "ran",
+                PipeStatus::Skipped => "skipped",
+                PipeStatus::Stopped => "stopped",
+            };
+            write!(
+                f,
+                "\n  #{} {} {} +{:?} {:?}",
+                pipe.index,
+                pipe.name.as_deref().unwrap_or("-"),
+                status,
+                pipe.offset,
+                pipe.duration
+            )?;
+            if pipe.attempts > 1 {
+                write!(f, " x{}", pipe.attempts)?;
+            }
+            if !pipe.stored.is_empty() {
+                write!(f, " stored [{}]", pipe.stored.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Pipeline, PipelineBuilder, PipelineTrait};
+
+    #[tokio::test]
+    async fn test_report() {
+        let builder = PipelineBuilder::<u32>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .named("slow")
+                        .through_fn(|| async {
+                            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                        })
+                        .await
+                        .named("label")
+                        .store_fn(|num: u32| async move { format!("#{}", num) })
+                        .await
+                        .named("check")
+                        .next_fn(|num: u32| async move { num > 1 })
+                        .await
+                        .named("never")
+                        .store_fn(|num: u32| async move { num + 1 })
+                        .await
+                })
+            })
+            .await;
+
+        let report = builder.report(1).await;
+        assert_eq!(
+            report.slowest().and_then(|p| p.name.as_deref()),
+            Some("slow")
+        );
+        assert_eq!(report.pipes[1].stored, ["alloc::string::String"]);
+        assert!(report.pipes[1].offset >= report.pipes[0].duration);
+        assert!(report.pipes[3].stored.is_empty());
+
+        let rendered = report.to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(
+            lines[0].starts_with("pipeline<u32> stopped at pipe 2: the pipe returned false in ")
+        );
+        assert!(lines[2].starts_with("  #1 label ran +"));
+        assert!(lines[2].ends_with(" stored [alloc::string::String]"));
+        assert!(lines[4].starts_with("  #3 never skipped +"));
+        assert!(lines[4].ends_with(" 0ns"));
+
+        let pipeline = Pipeline::pass(()).await;
+        assert!(pipeline.report().pipes.is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    any::Any,
+    hash::{BuildHasher, RandomState},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type RetryPredicate = Arc<dyn Fn(&(dyn Any + Send + Sync)) -> bool + Send + Sync>;
+
+/// How long to wait between two attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// Always wait the same amount of time
+    Fixed(Duration),
+    /// Double the wait after each attempt, up to `max`
+    Exponential { initial: Duration, max: Duration },
+}
+
+/// Decides when and how often an `ok` or `ok_fn` pipe is called again
+/// after it returned an `Err`
+///
+/// ```rust
+///# use std::time::Duration;
+///# use fama::{Pipeline, RetryPolicy};
+///
+/// #[derive(Debug, Clone)]
+/// enum DbError {
+///     Busy,
+///     NotFound,
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let pipeline = Pipeline::pass(0)
+///         .await
+///         .retry(
+///             RetryPolicy::exponential(Duration::from_millis(1))
+///                 .max_attempts(5)
+///                 .retry_if(|error: &DbError| matches!(error, DbError::Busy)),
+///         )
+///         .ok_fn(|| async { Err::<i32, DbError>(DbError::NotFound) })
+///         .await;
+///
+///     // `NotFound` is not retryable
+///     assert_eq!(pipeline.execution_log()[0].attempts, 1);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    jitter: bool,
+    retry_if: Option<RetryPredicate>,
+}
+
+impl RetryPolicy {
+    /// Waits the same `delay` between attempts
+    pub fn fixed(delay: Duration) -> Self {
+        Self::new(Backoff::Fixed(delay))
+    }
+
+    /// Waits `initial`, then twice as long after each attempt.
+    /// The wait is capped at 30 seconds unless `max_delay` is set
+    pub fn exponential(initial: Duration) -> Self {
+        Self::new(Backoff::Exponential {
+            initial,
+            max: Duration::from_secs(30),
+        })
+    }
+
+    fn new(backoff: Backoff) -> Self {
+        Self {
+            max_attempts: 3,
+            backoff,
+            jitter: false,
+            retry_if: None,
+        }
+    }
+
+    /// The maximum number of calls, including the first one. Defaults to 3
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Caps the wait of an exponential backoff
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        if let Backoff::Exponential { max, .. } = &mut self.backoff {
+            *max = max_delay;
+        }
+        self
+    }
+
+    /// Waits a random time between zero and the computed delay
+    pub fn with_jitter(mut self) -> Self {
+        self.jitter = true;
+        self
+    }
+
+    /// Only retries errors for which `predicate` returns true.
+    /// Errors of another type than `E` are not retried.
+    /// By default every error is retried
+    pub fn retry_if<E: 'static>(
+        mut self,
+        predicate: impl Fn(&E) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retry_if = Some(Arc::new(move |error| {
+            error.downcast_ref::<E>().is_some_and(&predicate)
+        }));
+        self
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    pub(crate) fn should_retry(&self, attempt: u32, error: &(dyn Any + Send + Sync)) -> bool {
+        attempt < self.max_attempts
+            && self
+                .retry_if
+                .as_ref()
+                .is_none_or(|predicate| predicate(error))
+    }
+
+    /// The wait after the given attempt. Attempts start at 1
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => initial
+                .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+                .min(max),
+        };
+
+        if self.jitter {
+            let random = RandomState::new().hash_one(Instant::now());
+            delay.mul_f64(random as f64 / u64::MAX as f64)
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exponential_delay() {
+        let policy = RetryPolicy::exponential(Duration::from_millis(10))
+            .max_delay(Duration::from_millis(50));
+
+        assert_eq!(policy.delay(1), Duration::from_millis(10));
+        assert_eq!(policy.delay(2), Duration::from_millis(20));
+        assert_eq!(policy.delay(3), Duration::from_millis(40));
+        assert_eq!(policy.delay(4), Duration::from_millis(50));
+        assert_eq!(policy.delay(40), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_jitter_stays_below_delay() {
+        let policy = RetryPolicy::fixed(Duration::from_millis(10)).with_jitter();
+
+        for attempt in 1..20 {
+            assert!(policy.delay(attempt) <= Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::fixed(Duration::ZERO)
+            .max_attempts(3)
+            .retry_if(|error: &&str| *error == "busy");
+
+        assert!(policy.should_retry(1, &"busy"));
+        assert!(policy.should_retry(2, &"busy"));
+        assert!(!policy.should_retry(3, &"busy"));
+        assert!(!policy.should_retry(1, &"not found"));
+        assert!(!policy.should_retry(1, &500));
+    }
+}
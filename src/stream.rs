@@ -0,0 +1,48 @@
+use crate::CancellationToken;
+
+/// How `PipelineDef::run_stream` processes the items of a stream
+///
+/// By default the items are processed one at a time and the results
+/// come out in the order the items came in.
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+    pub(crate) concurrency: usize,
+    pub(crate) ordered: bool,
+    pub(crate) cancellation: Option<CancellationToken>,
+}
+
+impl StreamOptions {
+    pub fn new() -> Self {
+        Self {
+            concurrency: 1,
+            ordered: true,
+            cancellation: None,
+        }
+    }
+
+    /// Processes up to `limit` items at the same time
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit.max(1);
+        self
+    }
+
+    /// Yields the results as soon as they are ready instead of
+    /// in the order of the items
+    pub fn unordered(mut self) -> Self {
+        self.ordered = false;
+        self
+    }
+
+    /// Cancels the items that have not gone through all the pipes yet
+    /// when `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,190 @@
+//! Spans for the optional `tracing` feature. Without the feature the
+//! functions compile down to nothing
+
+use std::future::Future;
+
+use crate::PipeStatus;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Span;
+
+/// Opens the span of a pipeline run
+#[cfg(feature = "tracing")]
+pub(crate) fn pipeline_span(content: &str) -> Span {
+    tracing::info_span!("pipeline", content)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn pipeline_span(_: &str) -> Span {
+    Span
+}
+
+/// Opens the span of a pipe as a child of the pipeline span
+#[cfg(feature = "tracing")]
+pub(crate) fn pipe_span(pipeline: &Span, index: usize, name: Option<&str>, content: &str) -> Span {
+    tracing::info_span!(
+        parent: pipeline,
+        "pipe",
+        index,
+        name = name.unwrap_or_default(),
+        content,
+        result = tracing::field::Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn pipe_span(_: &Span, _: usize, _: Option<&str>, _: &str) -> Span {
+    Span
+}
+
+/// Records what happened to the pipe on its span
+#[cfg(feature = "tracing")]
+pub(crate) fn record_status(span: &Span, status: PipeStatus) {
+    let result = match status {
+        PipeStatus::Ran => "ran",
+        PipeStatus::Skipped => "skipped",
+        PipeStatus::Stopped => "stopped",
+    };
+    span.record("result", result);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_status(_: &Span, _: PipeStatus) {}
+
+/// Enters `span` each time `future` is polled, so the events and
+/// the nested pipelines of a pipe belong to its span
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(span: &Span, future: F) -> impl Future<Output = F::Output> {
+    tracing::Instrument::instrument(future, span.clone())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(_: &Span, future: F) -> impl Future<Output = F::Output> {
+    future
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    };
+
+    use tracing::{
+        Event, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+    };
+
+    use crate::{Pipeline, PipelineDef};
+
+    /// Keeps the name, the parent and the fields of every span
+    #[derive(Clone, Default)]
+    struct Collector {
+        next_id: Arc<AtomicU64>,
+        spans: Arc<Mutex<Vec<CollectedSpan>>>,
+        entered: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct CollectedSpan {
+        name: &'static str,
+        parent: Option<u64>,
+        fields: Vec<(&'static str, String)>,
+    }
+
+    impl CollectedSpan {
+        fn field(&self, name: &str) -> Option<&str> {
+            self.fields
+                .iter()
+                .rev()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    impl Visit for CollectedSpan {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields.push((field.name(), format!("{:?}", value)));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut span = CollectedSpan {
+                name: attributes.metadata().name(),
+                parent: match attributes.parent() {
+                    Some(parent) => Some(parent.into_u64()),
+                    None if attributes.is_contextual() => {
+                        self.entered.lock().unwrap().last().copied()
+                    }
+                    None => None,
+                },
+                ..Default::default()
+            };
+            attributes.record(&mut span);
+            self.spans.lock().unwrap().push(span);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, id: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[id.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, id: &Id) {
+            self.entered.lock().unwrap().push(id.into_u64());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spans() {
+        let collector = Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        let def = PipelineDef::new().store_fn(|num: i32| async move { num * 2 });
+        Pipeline::pass(1)
+            .await
+            .through_pipeline(def)
+            .await
+            .next_fn(|| async { false })
+            .await
+            .store_fn(|num: i32| async move { num + 1 })
+            .await;
+
+        let spans = collector.spans.lock().unwrap();
+        let pipes = spans.iter().filter(|span| span.name == "pipe");
+        let results: Vec<_> = pipes.map(|span| span.field("result").unwrap()).collect();
+        assert_eq!(results, ["ran", "ran", "stopped", "skipped"]);
+
+        assert_eq!(spans[0].name, "pipeline");
+        assert_eq!(spans[0].field("content"), Some("i32"));
+        assert_eq!(spans[1].parent, Some(1));
+        assert!(spans[1].field("name").unwrap().contains("PipelineDef<i32>"));
+
+        // The nested pipeline belongs to the pipe that runs it
+        assert_eq!(spans[2].name, "pipeline");
+        assert_eq!(spans[3].parent, Some(3));
+        assert!(spans[3].field("name").unwrap().contains("test_spans"));
+    }
+}
@@ -0,0 +1,468 @@
+use std::{marker::PhantomData, time::Duration};
+
+use futures::future::{Ready, ready};
+
+use crate::{
+    ExecutionLog, FamaPipe, FlowOutcome, PipeContent, Pipeline, Reason, RetryPolicy,
+    pipeline::{Flow, PipeFnHandler},
+};
+
+/// A pipeline whose pipes return `Result<_, E>`
+///
+/// A pipe may return any error that converts into `E`, so the `?`
+/// operator can be used inside of it. The first error fails the flow
+/// and `deliver` returns it. Any other way the flow can end, such as
+/// a panic or a timeout, reaches the caller through `From<FlowOutcome>`.
+///
+/// ```rust
+///# use std::num::ParseIntError;
+///# use fama::{FlowOutcome, TryPipeline};
+/// #[derive(Debug, Clone, PartialEq)]
+/// enum ApiError {
+///     BadRequest(String),
+///     Internal,
+/// }
+///
+/// impl From<ParseIntError> for ApiError {
+///     fn from(error: ParseIntError) -> Self {
+///         Self::BadRequest(error.to_string())
+///     }
+/// }
+///
+/// impl From<FlowOutcome> for ApiError {
+///     fn from(_: FlowOutcome) -> Self {
+///         Self::Internal
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let quantity = TryPipeline::<String, ApiError>::pass("12x".to_string())
+///         .await
+///         .store_fn(|raw: String| async move {
+///             let quantity: u32 = raw.parse()?;
+///             Ok::<_, ParseIntError>(quantity)
+///         })
+///         .await
+///         .deliver()
+///         .await;
+///
+///     assert!(matches!(quantity, Err(ApiError::BadRequest(_))));
+/// }
+/// ```
+pub struct TryPipeline<T: Send + Sync + 'static, E> {
+    pipeline: Pipeline<T>,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<T, E> TryPipeline<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: From<FlowOutcome> + Clone + Send + Sync + 'static,
+{
+    /// Accepts the pipeline content/input.
+    /// This is the beginning of the pipeline
+    pub async fn pass(content: T) -> Self {
+        Self::new(Pipeline::pass(content).await)
+    }
+
+    pub(crate) fn new(pipeline: Pipeline<T>) -> Self {
+        Self {
+            pipeline,
+            error: PhantomData,
+        }
+    }
+
+    /// See `Pipeline::catch_panics`
+    pub fn catch_panics(self) -> Self {
+        Self::new(self.pipeline.catch_panics())
+    }
+
+    /// See `Pipeline::timeout`
+    pub fn timeout(self, duration: Duration) -> Self {
+        Self::new(self.pipeline.timeout(duration))
+    }
+
+    /// See `Pipeline::retry`
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        Self::new(self.pipeline.retry(policy))
+    }
+
+    /// See `Pipeline::named`
+    pub fn named(self, name: impl Into<String>) -> Self {
+        Self::new(self.pipeline.named(name))
+    }
+
+    /// See `Pipeline::describe`
+    pub fn describe(self, description: impl Into<String>) -> Self {
+        Self::new(self.pipeline.describe(description))
+    }
+
+    /// See `Pipeline::tag`
+    pub fn tag(self, tag: impl Into<String>) -> Self {
+        Self::new(self.pipeline.tag(tag))
+    }
+
+    /// Accepts a closure or function as a pipe. The `Ok` value is dropped
+    pub async fn through_fn<H, Args, O, PE>(self, mut handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<O, PE>>,
+        Args: busybody::Resolver + 'static,
+        PE: Send + Sync + 'static,
+        E: From<PE>,
+    {
+        let pipeline = self
+            .pipeline
+            .name_pipe::<H>()
+            .run_result_pipe(
+                |args| handler.pipe_fn_handle(args),
+                settle_through::<O, PE, E>,
+            )
+            .await;
+        Self::new(pipeline)
+    }
+
+    /// Accepts a closure or function as a pipe. The `Ok` value is stored
+    pub async fn store_fn<H, Args, O, PE>(self, mut handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<O, PE>>,
+        Args: busybody::Resolver + 'static,
+        O: Clone + Send + Sync + 'static,
+        PE: Send + Sync + 'static,
+        E: From<PE>,
+    {
+        let pipeline = self
+            .pipeline
+            .name_pipe::<H>()
+            .run_result_pipe(
+                |args| handler.pipe_fn_handle(args),
+                settle_store::<O, PE, E>,
+            )
+            .await;
+        Self::new(pipeline)
+    }
+
+    /// Accepts an instance of a struct that implements `fama::FamaPipe`.
+    /// The `Ok` value is dropped
+    pub async fn through<H, Args, O, PE>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Result<O, PE>>,
+        Args: busybody::Resolver + 'static,
+        PE: Send + Sync + 'static,
+        E: From<PE>,
+    {
+        let pipeline = self
+            .pipeline
+            .describe_pipe(&handler)
+            .run_result_pipe(
+                |args| handler.receive_pipe_content(args),
+                settle_through::<O, PE, E>,
+            )
+            .await;
+        Self::new(pipeline)
+    }
+
+    /// Accepts an instance of a struct that implements `fama::FamaPipe`.
+    /// The `Ok` value is stored
+    pub async fn store<H, Args, O, PE>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Result<O, PE>>,
+        Args: busybody::Resolver + 'static,
+        O: Clone + Send + Sync + 'static,
+        PE: Send + Sync + 'static,
+        E: From<PE>,
+    {
+        let pipeline = self
+            .pipeline
+            .describe_pipe(&handler)
+            .run_result_pipe(
+                |args| handler.receive_pipe_content(args),
+                settle_store::<O, PE, E>,
+            )
+            .await;
+        Self::new(pipeline)
+    }
+
+    /// Accepts a closure or function as a pipe that returns a `bool`.
+    /// See `Pipeline::next_fn`
+    pub async fn next_fn<H, Args>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.next_fn(handler).await)
+    }
+
+    /// See `Pipeline::next`
+    pub async fn next<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.next(handler).await)
+    }
+
+    /// Accepts a closure or function as a pipe that returns an `Option`.
+    /// See `Pipeline::some_fn`
+    pub async fn some_fn<H, Args, O>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Option<O>>,
+        Args: busybody::Resolver + 'static,
+        O: Clone + Send + Sync + 'static,
+    {
+        Self::new(self.pipeline.some_fn(handler).await)
+    }
+
+    /// See `Pipeline::some`
+    pub async fn some<H, Args, O>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, Option<O>>,
+        Args: busybody::Resolver + 'static,
+        O: Clone + Send + Sync + 'static,
+    {
+        Self::new(self.pipeline.some(handler).await)
+    }
+
+    /// Turns the content into a `U`. See `Pipeline::map_fn`
+    pub async fn map_fn<H, Args, U>(self, handler: H) -> TryPipeline<U, E>
+    where
+        H: PipeFnHandler<Args, U>,
+        Args: busybody::Resolver + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        TryPipeline::new(self.pipeline.map_fn(handler).await)
+    }
+
+    /// See `Pipeline::map`
+    pub async fn map<H, Args, U>(self, handler: H) -> TryPipeline<U, E>
+    where
+        H: FamaPipe<Args, U>,
+        Args: busybody::Resolver + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        TryPipeline::new(self.pipeline.map(handler).await)
+    }
+
+    /// See `Pipeline::recover_fn`
+    pub async fn recover_fn<H, Args>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.recover_fn(handler).await)
+    }
+
+    /// See `Pipeline::recover`
+    pub async fn recover<H, Args>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, bool>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.recover(handler).await)
+    }
+
+    /// See `Pipeline::finally_fn`
+    pub async fn finally_fn<H, Args, O>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, O>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.finally_fn(handler).await)
+    }
+
+    /// See `Pipeline::finally`
+    pub async fn finally<H, Args, O>(self, handler: H) -> Self
+    where
+        H: FamaPipe<Args, O>,
+        Args: busybody::Resolver + 'static,
+    {
+        Self::new(self.pipeline.finally(handler).await)
+    }
+
+    /// See `Pipeline::compensate_fn`
+    pub fn compensate_fn<H, Args, PE>(self, handler: H) -> Self
+    where
+        H: PipeFnHandler<Args, Result<(), PE>>,
+        Args: busybody::Resolver + Send + 'static,
+        PE: Send + Sync + 'static,
+    {
+        Self::new(self.pipeline.compensate_fn(handler))
+    }
+
+    /// Runs the compensations of a flow that did not complete and
+    /// records the metrics. See `Pipeline::finish`
+    pub async fn finish(self) -> Self {
+        Self::new(self.pipeline.finish().await)
+    }
+
+    /// Finishes the pipeline and returns the content, or the error the
+    /// flow ended with
+    pub async fn deliver(self) -> Result<T, E> {
+        self.pipeline.finish().await.deliver_result().await
+    }
+
+    /// See `Pipeline::outcome`
+    pub fn outcome(&self) -> &FlowOutcome {
+        self.pipeline.outcome()
+    }
+
+    /// See `Pipeline::execution_log`
+    pub fn execution_log(&self) -> &ExecutionLog {
+        self.pipeline.execution_log()
+    }
+
+    /// Returns the untyped pipeline, to use the steps `TryPipeline` lacks.
+    /// `store_fn` and `store` take the place of `Pipeline::ok_fn` and
+    /// `Pipeline::ok`, and fail the flow with an `E`
+    pub fn into_pipeline(self) -> Pipeline<T> {
+        self.pipeline
+    }
+}
+
+fn settle_through<O, PE, E>(result: Result<O, PE>, _: PipeContent) -> Ready<Flow>
+where
+    E: From<PE> + Send + Sync + 'static,
+{
+    ready(match result {
+        Ok(_) => Flow::Continue,
+        Err(error) => Flow::Fail(Reason::new(E::from(error))),
+    })
+}
+
+async fn settle_store<O, PE, E>(result: Result<O, PE>, pipe: PipeContent) -> Flow
+where
+    O: Clone + Send + Sync + 'static,
+    E: From<PE> + Send + Sync + 'static,
+{
+    match result {
+        Ok(value) => {
+            pipe.store(value).await;
+            Flow::Continue
+        }
+        Err(error) => Flow::Fail(Reason::new(E::from(error))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{PipelineDef, PipelineTrait};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum ImportError {
+        InvalidRow(String),
+        Rejected,
+    }
+
+    impl From<std::num::ParseIntError> for ImportError {
+        fn from(error: std::num::ParseIntError) -> Self {
+            Self::InvalidRow(error.to_string())
+        }
+    }
+
+    impl From<FlowOutcome> for ImportError {
+        fn from(_: FlowOutcome) -> Self {
+            Self::Rejected
+        }
+    }
+
+    struct Parse;
+    #[crate::async_trait]
+    impl FamaPipe<String, Result<i64, std::num::ParseIntError>> for Parse {
+        async fn receive_pipe_content(&self, row: String) -> Result<i64, std::num::ParseIntError> {
+            row.trim().parse()
+        }
+    }
+
+    async fn import(row: &str) -> TryPipeline<String, ImportError> {
+        TryPipeline::pass(row.to_string())
+            .await
+            .store(Parse)
+            .await
+            .through_fn(|amount: i64| async move {
+                if amount < 0 {
+                    return Err(ImportError::InvalidRow("negative amount".to_string()));
+                }
+                Ok(())
+            })
+            .await
+            .store_fn(
+                |amount: i64| async move { Ok::<_, ImportError>(format!("{} cents", amount)) },
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        assert_eq!(
+            import(" 20").await.deliver().await,
+            Ok("20 cents".to_string())
+        );
+        assert_eq!(
+            import("-1").await.deliver().await,
+            Err(ImportError::InvalidRow("negative amount".to_string()))
+        );
+
+        let pipeline = import("abc").await;
+        assert_eq!(pipeline.outcome().at_pipe(), Some(0));
+        assert_eq!(pipeline.execution_log().len(), 3);
+        assert!(matches!(
+            pipeline.deliver().await,
+            Err(ImportError::InvalidRow(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deliver_finishes() {
+        let undone = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = undone.clone();
+        let result = TryPipeline::<String, ImportError>::pass("12".to_string())
+            .await
+            .map_fn(|raw: String| async move { raw.len() as i64 })
+            .await
+            .next_fn(|len: i64| async move { len > 0 })
+            .await
+            .compensate_fn(move || {
+                let flag = flag.clone();
+                async move {
+                    flag.store(true, std::sync::atomic::Ordering::SeqCst);
+                    Ok::<(), ImportError>(())
+                }
+            })
+            .store_fn(|| async { Err::<i64, _>(ImportError::Rejected) })
+            .await
+            .deliver()
+            .await;
+
+        assert_eq!(result, Err(ImportError::Rejected));
+        assert!(undone.load(std::sync::atomic::Ordering::SeqCst));
+
+        let recovered = TryPipeline::<i64, ImportError>::pass(1)
+            .await
+            .store_fn(|| async { Err::<i64, _>(ImportError::Rejected) })
+            .await
+            .recover_fn(|| async { true })
+            .await;
+        assert_eq!(recovered.deliver().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_other_outcomes() {
+        let pipeline = TryPipeline::<i32, ImportError>::pass(1)
+            .await
+            .through_fn(|pipe: PipeContent| async move {
+                pipe.stop_the_flow().await;
+                Ok::<_, ImportError>(())
+            })
+            .await;
+        assert_eq!(pipeline.deliver().await, Err(ImportError::Rejected));
+
+        let def = PipelineDef::new().ok_fn(|num: i32| async move {
+            Err::<i32, _>(ImportError::InvalidRow(num.to_string()))
+        });
+        assert_eq!(
+            def.deliver_result::<ImportError>(7).await,
+            Err(ImportError::InvalidRow("7".to_string()))
+        );
+    }
+}
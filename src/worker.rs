@@ -0,0 +1,282 @@
+use std::{
+    fmt::{Debug, Display},
+    pin::pin,
+    sync::Arc,
+};
+
+use futures::future::{Either, select};
+use tokio::{
+    sync::{Mutex, mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{CancellationToken, Delivered, Pipeline, PipelineBuilder};
+
+/// The content could not be queued because the worker was shut down.
+/// The content is handed back
+pub struct WorkerClosed<T>(pub T);
+
+impl<T> Debug for WorkerClosed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WorkerClosed(..)")
+    }
+}
+
+impl<T> Display for WorkerClosed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the pipeline worker has been shut down")
+    }
+}
+
+impl<T> std::error::Error for WorkerClosed<T> {}
+
+struct Job<T> {
+    content: T,
+    reply: Option<oneshot::Sender<Delivered<T>>>,
+}
+
+/// Sends contents to a `PipelineWorker`. Cheap to clone
+pub struct WorkerQueue<T> {
+    sender: mpsc::Sender<Job<T>>,
+}
+
+impl<T> Clone for WorkerQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> WorkerQueue<T> {
+    /// Queues the content. Waits while the queue is full
+    pub async fn submit(&self, content: T) -> Result<(), WorkerClosed<T>> {
+        self.send(Job {
+            content,
+            reply: None,
+        })
+        .await
+    }
+
+    /// Queues the content. The returned receiver gets the delivered
+    /// content and how its flow ended
+    pub async fn request(
+        &self,
+        content: T,
+    ) -> Result<oneshot::Receiver<Delivered<T>>, WorkerClosed<T>> {
+        let (reply, receiver) = oneshot::channel();
+        self.send(Job {
+            content,
+            reply: Some(reply),
+        })
+        .await?;
+
+        Ok(receiver)
+    }
+
+    async fn send(&self, job: Job<T>) -> Result<(), WorkerClosed<T>> {
+        self.sender
+            .send(job)
+            .await
+            .map_err(|error| WorkerClosed(error.0.content))
+    }
+}
+
+/// Runs the contents sent through a bounded queue through a
+/// `PipelineBuilder`, using a fixed number of tasks
+///
+/// Sending waits while the queue is full. Panics are caught per content,
+/// so a panicking pipe does not take a task down.
+///
+/// ```rust
+///# use fama::{PipelineBuilder, PipelineWorker};
+///# #[tokio::main]
+///# async fn main() {
+/// let builder = PipelineBuilder::<u64>::new();
+/// builder
+///     .register(|pipeline| {
+///         Box::pin(async { pipeline.store_fn(|job: u64| async move { job * 2 }).await })
+///     })
+///     .await;
+///
+/// let worker = PipelineWorker::spawn(builder, 4, 16);
+/// worker.submit(1).await.unwrap();
+/// let reply = worker.request(21).await.unwrap();
+///
+/// assert_eq!(reply.await.unwrap().content, 42);
+/// worker.shutdown().await;
+///# }
+/// ```
+pub struct PipelineWorker<T> {
+    queue: WorkerQueue<T>,
+    shutdown: CancellationToken,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> PipelineWorker<T> {
+    /// Starts `workers` tasks reading from a queue that holds up
+    /// to `capacity` contents
+    pub fn spawn(builder: PipelineBuilder<T>, workers: usize, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shutdown = CancellationToken::new();
+
+        let tasks = (0..workers.max(1))
+            .map(|_| {
+                let (builder, receiver, shutdown) =
+                    (builder.clone(), receiver.clone(), shutdown.clone());
+                tokio::spawn(async move {
+                    while let Some(job) = next_job(&receiver, &shutdown).await {
+                        process(&builder, job).await;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            queue: WorkerQueue { sender },
+            shutdown,
+            tasks,
+        }
+    }
+
+    /// Returns a queue that other tasks can use to send contents
+    pub fn queue(&self) -> WorkerQueue<T> {
+        self.queue.clone()
+    }
+
+    /// See `WorkerQueue::submit`
+    pub async fn submit(&self, content: T) -> Result<(), WorkerClosed<T>> {
+        self.queue.submit(content).await
+    }
+
+    /// See `WorkerQueue::request`
+    pub async fn request(
+        &self,
+        content: T,
+    ) -> Result<oneshot::Receiver<Delivered<T>>, WorkerClosed<T>> {
+        self.queue.request(content).await
+    }
+
+    /// Stops accepting contents, processes the ones already queued
+    /// and waits for the tasks to finish
+    pub async fn shutdown(self) {
+        self.shutdown.cancel();
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Waits for the next job. Once the worker is shut down, the queue is
+/// closed and the jobs left in it are handed out until it is empty
+async fn next_job<T>(
+    receiver: &Mutex<mpsc::Receiver<Job<T>>>,
+    shutdown: &CancellationToken,
+) -> Option<Job<T>> {
+    let mut receiver = receiver.lock().await;
+    if !shutdown.is_cancelled() {
+        let recv = pin!(receiver.recv());
+        let cancelled = pin!(shutdown.cancelled());
+        if let Either::Left((job, _)) = select(recv, cancelled).await {
+            return job;
+        }
+    }
+
+    receiver.close();
+    receiver.recv().await
+}
+
+async fn process<T: Clone + Send + Sync + 'static>(builder: &PipelineBuilder<T>, job: Job<T>) {
+    let pipeline = Pipeline::pass(job.content).await.catch_panics();
+    let pipeline = builder
+        .definition()
+        .await
+        .run_on(pipeline)
+        .await
+        .finish()
+        .await;
+
+    if let Some(reply) = job.reply {
+        let _ = reply.send(Delivered {
+            content: pipeline.deliver().await,
+            outcome: pipeline.outcome().clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    static PROCESSED: AtomicUsize = AtomicUsize::new(0);
+
+    #[tokio::test]
+    async fn test_shutdown_drains_the_queue() {
+        let builder = PipelineBuilder::<i16>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .through_fn(|| async {
+                            tokio::time::sleep(Duration::from_millis(5)).await;
+                            PROCESSED.fetch_add(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .await;
+
+        let worker = PipelineWorker::spawn(builder, 2, 4);
+        let queue = worker.queue();
+        for item in 0..10 {
+            queue.submit(item).await.unwrap();
+        }
+
+        worker.shutdown().await;
+        assert_eq!(PROCESSED.load(Ordering::SeqCst), 10);
+        assert!(matches!(queue.submit(11).await, Err(WorkerClosed(11))));
+    }
+
+    #[tokio::test]
+    async fn test_request_reply() {
+        let builder = PipelineBuilder::<i8>::new();
+        builder
+            .register(|pipeline| {
+                Box::pin(async {
+                    pipeline
+                        .next_fn(|num: i8| async move {
+                            if num == 0 {
+                                panic!("zero");
+                            }
+                            num > 0
+                        })
+                        .await
+                        .store_fn(|num: i8| async move { num - 1 })
+                        .await
+                })
+            })
+            .await;
+
+        let worker = PipelineWorker::spawn(builder, 1, 1);
+        let replies = vec![
+            worker.request(5).await.unwrap(),
+            worker.request(0).await.unwrap(),
+            worker.request(-5).await.unwrap(),
+        ];
+
+        let mut delivered = Vec::new();
+        for reply in replies {
+            delivered.push(reply.await.unwrap());
+        }
+
+        assert_eq!(delivered[0].content, 4);
+        assert!(delivered[1].outcome.is_failed());
+        assert!(delivered[2].outcome.is_stopped());
+        worker.shutdown().await;
+    }
+}